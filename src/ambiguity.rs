@@ -0,0 +1,229 @@
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+
+use crate::bnf::rule_to_bnf;
+use crate::forest::{Child, Forest, NodeId};
+use crate::grammar::{Rule, Symbol};
+use crate::tree::ParseTree;
+
+// A non-terminal deriving a span of the input in more than one way, differing at the top of the
+// span rather than somewhere below it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Ambiguity {
+    pub symbol: Symbol,
+    pub span: (usize, usize),
+    // The grammar rules of the competing derivations
+    pub rules: Vec<Rule>,
+    // A tree for each competing derivation, using the first derivation of every node below
+    pub alternatives: Vec<ParseTree>,
+    // The two alternatives with the fewest differing children
+    pub minimal_pair: (usize, usize),
+}
+
+// Every ambiguity of a forest, ordered by span
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AmbiguityReport {
+    pub ambiguities: Vec<Ambiguity>,
+}
+
+impl AmbiguityReport {
+    pub fn new(forest: &Forest) -> AmbiguityReport {
+        // The complete nodes of each visible non-terminal and span
+        let mut symbol_nodes: BTreeMap<((usize, usize), &Symbol), Vec<NodeId>> = BTreeMap::new();
+        for id in (0..forest.len()).map(NodeId) {
+            let node = forest.get_node(id);
+            let lhs = forest.get_grammar().rule(node.rule).lhs;
+            if forest.is_complete(id) && !forest.get_grammar().is_hidden(lhs) {
+                symbol_nodes
+                    .entry((node.span, forest.get_symbol(id)))
+                    .or_default()
+                    .push(id);
+            }
+        }
+
+        let mut ambiguities = Vec::new();
+        for ((span, symbol), ids) in symbol_nodes {
+            // Derivations whose children only differ below the top of the span are the same
+            // here, so keep one for each rule and split of the span
+            let mut derivations = Vec::new();
+            let mut seen = HashSet::new();
+            for id in ids {
+                for children in local_derivations(forest, id, &mut Vec::new()) {
+                    let key = (id, child_keys(forest, &children));
+                    if seen.insert(key.clone()) {
+                        derivations.push((key, children));
+                    }
+                }
+            }
+            if derivations.len() < 2 {
+                continue;
+            }
+
+            let mut rules = Vec::new();
+            for &((id, _), _) in &derivations {
+                let rule = forest.get_rule(id);
+                if !rules.contains(&rule) {
+                    rules.push(rule);
+                }
+            }
+
+            let alternatives = derivations
+                .iter()
+                .map(|(_, children)| build_alternative(forest, symbol, span, children))
+                .collect();
+
+            ambiguities.push(Ambiguity {
+                symbol: symbol.clone(),
+                span,
+                rules,
+                alternatives,
+                minimal_pair: minimal_pair(&derivations),
+            });
+        }
+
+        AmbiguityReport { ambiguities }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ambiguities.is_empty()
+    }
+}
+
+impl Forest {
+    pub fn get_ambiguity_report(&self) -> AmbiguityReport {
+        AmbiguityReport::new(self)
+    }
+}
+
+// The sequences of children the node can be derived with, looking through its left nodes and
+// splicing in the children of hidden helper non-terminals, but not looking below its other
+// children. `path` holds the hidden nodes being spliced, to leave out cyclic derivations.
+fn local_derivations(forest: &Forest, id: NodeId, path: &mut Vec<NodeId>) -> Vec<Vec<Child>> {
+    if path.contains(&id) {
+        return Vec::new();
+    }
+    path.push(id);
+
+    let mut derivations = Vec::new();
+    for packed in forest.get_alternatives(id) {
+        let lefts = match packed.left {
+            Some(left) => local_derivations(forest, left, path),
+            None => vec![Vec::new()],
+        };
+        let rights = match packed.right {
+            Some(Child::Node(child)) if is_hidden(forest, child) => {
+                local_derivations(forest, child, path)
+            }
+            Some(child) => vec![vec![child]],
+            None => vec![Vec::new()],
+        };
+
+        for left in &lefts {
+            for right in &rights {
+                derivations.push([&left[..], &right[..]].concat());
+            }
+        }
+    }
+
+    path.pop();
+    derivations
+}
+
+fn is_hidden(forest: &Forest, id: NodeId) -> bool {
+    let lhs = forest.get_grammar().rule(forest.get_node(id).rule).lhs;
+    forest.get_grammar().is_hidden(lhs)
+}
+
+fn build_alternative(
+    forest: &Forest,
+    symbol: &Symbol,
+    span: (usize, usize),
+    children: &[Child],
+) -> ParseTree {
+    let children: Vec<ParseTree> = children
+        .iter()
+        .map(|&child| match child {
+            Child::Node(id) => forest.get_tree(id),
+            Child::Terminal(position) => {
+                ParseTree::leaf(forest.get_terminal(position).clone(), position)
+            }
+        })
+        .collect();
+    let right_side = children.iter().map(|child| child.label.clone()).collect();
+
+    ParseTree {
+        label: symbol.clone(),
+        rule: Some((symbol.clone(), right_side)),
+        span,
+        children,
+    }
+}
+
+// Identifies the children by symbol and span, as the nodes of different rules of a non-terminal
+// are different nodes
+fn child_keys<'f>(forest: &'f Forest, children: &[Child]) -> Vec<(&'f Symbol, (usize, usize))> {
+    children
+        .iter()
+        .map(|&child| match child {
+            Child::Node(id) => (forest.get_symbol(id), forest.get_node(id).span),
+            Child::Terminal(position) => (forest.get_terminal(position), (position, position + 1)),
+        })
+        .collect()
+}
+
+type Derivation<'f> = ((NodeId, Vec<(&'f Symbol, (usize, usize))>), Vec<Child>);
+
+// Finds the two derivations with the fewest differing children, counting a differing rule as one
+fn minimal_pair(derivations: &[Derivation]) -> (usize, usize) {
+    let mut best = (0, 1);
+    let mut fewest = usize::MAX;
+
+    for a in 0..derivations.len() {
+        for b in a + 1..derivations.len() {
+            let ((node_a, keys_a), _) = &derivations[a];
+            let ((node_b, keys_b), _) = &derivations[b];
+            let differing = keys_a.iter().filter(|key| !keys_b.contains(key)).count()
+                + keys_b.iter().filter(|key| !keys_a.contains(key)).count()
+                + usize::from(node_a != node_b);
+            if differing < fewest {
+                fewest = differing;
+                best = (a, b);
+            }
+        }
+    }
+
+    best
+}
+
+impl fmt::Display for AmbiguityReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} ambiguous span(s)", self.ambiguities.len())?;
+        for ambiguity in &self.ambiguities {
+            write!(f, "\n\n{}", ambiguity)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Ambiguity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} over {}..{} has {} derivations",
+            self.symbol,
+            self.span.0,
+            self.span.1,
+            self.alternatives.len()
+        )?;
+        for rule in &self.rules {
+            write!(f, "\n  {}", rule_to_bnf(rule))?;
+        }
+
+        let (a, b) = self.minimal_pair;
+        write!(
+            f,
+            "\n  for example\n    {}\n    {}",
+            self.alternatives[a], self.alternatives[b]
+        )
+    }
+}
@@ -0,0 +1,163 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::grammar::{Grammar, Rule, Symbol};
+
+// Facts about the symbols of a grammar: the non-terminals that derive the empty string, the
+// terminals that the strings a symbol derives can start with (FIRST) and that can come after it
+// in a sentence (FOLLOW), and the left-corner relation. They are worked out together by adding to
+// each set until nothing changes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GrammarAnalysis {
+    nullable: HashSet<Symbol>,
+    // A rule deriving the empty string for each nullable non-terminal. The right side of that rule
+    // only has non-terminals that became nullable before it, so following the rules always
+    // bottoms out.
+    nullable_rules: HashMap<Symbol, Rule>,
+    // The FIRST set of every symbol. A terminal's is just itself.
+    first: HashMap<Symbol, HashSet<Symbol>>,
+    follow: HashMap<Symbol, HashSet<Symbol>>,
+    // The symbols that can come last in a sentence, whose FOLLOW sets take in the end of the input
+    at_end: HashSet<Symbol>,
+    // B is a left corner of A if A derives a string starting with B in one or more steps
+    left_corners: HashMap<Symbol, HashSet<Symbol>>,
+    // Returned for symbols that aren't in the grammar
+    empty: HashSet<Symbol>,
+}
+
+// Adds the items to the set, returning whether it grew
+fn add_all<'a>(set: &mut HashSet<Symbol>, items: impl IntoIterator<Item = &'a Symbol>) -> bool {
+    let before = set.len();
+    set.extend(items.into_iter().cloned());
+    set.len() > before
+}
+
+impl GrammarAnalysis {
+    pub fn new(grammar: &Grammar) -> GrammarAnalysis {
+        // Sorted so that the same rule is picked to derive the empty string from run to run
+        let mut rules: Vec<&Rule> = grammar.get_all_rules().iter().collect();
+        rules.sort();
+        let mut analysis = GrammarAnalysis {
+            nullable: HashSet::new(),
+            nullable_rules: HashMap::new(),
+            first: HashMap::new(),
+            follow: HashMap::new(),
+            at_end: HashSet::from([grammar.get_start().clone()]),
+            left_corners: HashMap::new(),
+            empty: HashSet::new(),
+        };
+        for symbol in grammar
+            .get_non_terminals()
+            .iter()
+            .chain(grammar.get_terminals())
+        {
+            let first = match symbol {
+                Symbol::Terminal(_) => HashSet::from([symbol.clone()]),
+                Symbol::NonTerminal(_) => HashSet::new(),
+            };
+            analysis.first.insert(symbol.clone(), first);
+            analysis.follow.insert(symbol.clone(), HashSet::new());
+            analysis.left_corners.insert(symbol.clone(), HashSet::new());
+        }
+
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for &rule in &rules {
+                let (lhs, right_side) = rule;
+                if !analysis.nullable.contains(lhs) && analysis.is_sequence_nullable(right_side) {
+                    analysis.nullable.insert(lhs.clone());
+                    analysis.nullable_rules.insert(lhs.clone(), rule.clone());
+                    changed = true;
+                }
+
+                let first = analysis.get_sequence_first(right_side);
+                changed |= add_all(analysis.first.get_mut(lhs).unwrap(), &first);
+            }
+        }
+
+        // FIRST and nullable are settled, so FOLLOW and the left corners can be worked out
+        changed = true;
+        while changed {
+            changed = false;
+            for &(lhs, right_side) in &rules {
+                for (position, symbol) in right_side.iter().enumerate() {
+                    let rest = &right_side[position + 1..];
+                    let mut follow = analysis.get_sequence_first(rest);
+                    if analysis.is_sequence_nullable(rest) {
+                        follow.extend(analysis.follow[lhs].iter().cloned());
+                        if analysis.at_end.contains(lhs) {
+                            changed |= analysis.at_end.insert(symbol.clone());
+                        }
+                    }
+                    changed |= add_all(analysis.follow.get_mut(symbol).unwrap(), &follow);
+
+                    // The symbols after a nullable prefix are left corners too
+                    if analysis.is_sequence_nullable(&right_side[..position]) {
+                        let mut corners = analysis.left_corners[symbol].clone();
+                        corners.insert(symbol.clone());
+                        changed |= add_all(analysis.left_corners.get_mut(lhs).unwrap(), &corners);
+                    }
+                }
+            }
+        }
+
+        analysis
+    }
+
+    pub fn get_nullable(&self) -> &HashSet<Symbol> {
+        &self.nullable
+    }
+
+    // The rule the nullable non-terminal derives the empty string with
+    pub fn get_nullable_rule(&self, symbol: &Symbol) -> Option<&Rule> {
+        self.nullable_rules.get(symbol)
+    }
+
+    pub fn is_nullable(&self, symbol: &Symbol) -> bool {
+        self.nullable.contains(symbol)
+    }
+
+    pub fn is_sequence_nullable(&self, symbols: &[Symbol]) -> bool {
+        symbols.iter().all(|symbol| self.is_nullable(symbol))
+    }
+
+    pub fn get_first(&self, symbol: &Symbol) -> &HashSet<Symbol> {
+        self.first.get(symbol).unwrap_or(&self.empty)
+    }
+
+    // The terminals that the strings the symbols derive can start with
+    pub fn get_sequence_first(&self, symbols: &[Symbol]) -> HashSet<Symbol> {
+        let mut first = HashSet::new();
+        for symbol in symbols {
+            first.extend(self.get_first(symbol).iter().cloned());
+            if !self.is_nullable(symbol) {
+                break;
+            }
+        }
+        first
+    }
+
+    pub fn get_follow(&self, symbol: &Symbol) -> &HashSet<Symbol> {
+        self.follow.get(symbol).unwrap_or(&self.empty)
+    }
+
+    // Whether the symbol can come last in a sentence, so that the end of the input follows it
+    pub fn can_end(&self, symbol: &Symbol) -> bool {
+        self.at_end.contains(symbol)
+    }
+
+    pub fn get_left_corners(&self, symbol: &Symbol) -> &HashSet<Symbol> {
+        self.left_corners.get(symbol).unwrap_or(&self.empty)
+    }
+
+    // Whether the symbol derives a string starting with the corner, in any number of steps
+    pub fn is_left_corner(&self, corner: &Symbol, symbol: &Symbol) -> bool {
+        corner == symbol || self.get_left_corners(symbol).contains(corner)
+    }
+}
+
+impl Grammar {
+    pub fn get_analysis(&self) -> GrammarAnalysis {
+        GrammarAnalysis::new(self)
+    }
+}
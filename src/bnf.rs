@@ -0,0 +1,949 @@
+use std::collections::HashSet;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+use crate::disambiguation::Associativity;
+use crate::ebnf::{EbnfRule, Expr};
+use crate::error::GrammarError;
+use crate::grammar::{Grammar, Rule, Symbol};
+use crate::lint::LintReport;
+
+// Loader for grammars written in a plain-text BNF notation:
+//
+//     # Comments run to the end of the line
+//     %start S
+//     S  ::= NP VP | VP
+//     NP ::= N | N PP
+//     N  ::= "they" | 'fish'
+//     A  ::= %empty | "a"
+//     VP ::= V NP? PP*
+//     B  ::= ("x" | "y")+
+//
+// Quoted symbols are terminals, bare names (or names wrapped in angle brackets, like <noun phrase>)
+// are non-terminals. Inside angle brackets \> and \\ stand for > and \, and \n and \t for a newline
+// and a tab, as they do in quoted terminals. The start symbol is the left side of the first rule
+// unless a %start directive says otherwise. Rules are separated by the next `name ::=`, so they can
+// be spread over several lines. The EBNF operators ?, *, + and parenthesised groups are lowered
+// into plain rules, see the ebnf module.
+//
+// Symbols that don't appear in any rule can be declared with %nonterminals A B and
+// %terminals "x" "y", and %hidden A B marks non-terminals to leave out of parse trees.
+//
+// Disambiguation filters name plain rules of the grammar, written like rules without EBNF
+// operators, see the disambiguation module:
+//
+//     %priority E ::= E "*" E > E ::= E "+" E | E "-" E
+//     %left E ::= E "+" E | E "-" E
+//     %right E ::= E "^" E
+//     %non-assoc E ::= E "<" E
+//     %reject Id ::= "if"
+//     %prefer S ::= "if" E S
+//     %avoid S ::= "if" E S "else" S
+//
+// Alternatives without EBNF operators can be given a weight, see the pcfg module. The weights of
+// each non-terminal's rules have to sum to 1 unless the grammar says %unnormalised.
+//
+//     NP ::= N [0.7] | N PP [0.3]
+
+#[derive(Debug, Clone, PartialEq)]
+enum TokenKind {
+    Name(String),
+    Quoted(String),
+    Define,
+    Alt,
+    Open,
+    Close,
+    Optional,
+    Star,
+    Plus,
+    Greater,
+    Weight(f64),
+    Directive(String),
+    End,
+}
+
+#[derive(Debug, Clone)]
+struct Token {
+    kind: TokenKind,
+    line: usize,
+    column: usize,
+}
+
+fn error_at(line: usize, column: usize, message: &str) -> GrammarError {
+    GrammarError::Syntax {
+        path: None,
+        line,
+        column,
+        message: message.to_string(),
+    }
+}
+
+// Characters allowed in a bare (unbracketed) name
+fn is_name_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_' || c == '-' || c == '.'
+}
+
+struct Lexer<'s> {
+    chars: std::iter::Peekable<std::str::Chars<'s>>,
+    line: usize,
+    column: usize,
+}
+
+impl<'s> Lexer<'s> {
+    fn peek(&mut self) -> Option<char> {
+        self.chars.peek().copied()
+    }
+
+    // Consumes the current character, keeping track of the position in the source
+    fn bump(&mut self) -> Option<char> {
+        let c = self.chars.next();
+        if c == Some('\n') {
+            self.line += 1;
+            self.column = 1;
+        } else if c.is_some() {
+            self.column += 1;
+        }
+        c
+    }
+
+    fn take_name(&mut self) -> String {
+        let mut name = String::new();
+        while let Some(c) = self.peek().filter(|&c| is_name_char(c)) {
+            name.push(c);
+            self.bump();
+        }
+        name
+    }
+
+    fn quoted(
+        &mut self,
+        quote: char,
+        line: usize,
+        column: usize,
+    ) -> Result<TokenKind, GrammarError> {
+        let mut value = String::new();
+        loop {
+            match self.bump() {
+                Some(c) if c == quote => break,
+                Some('\\') => match self.bump() {
+                    Some('n') => value.push('\n'),
+                    Some('t') => value.push('\t'),
+                    Some(c @ ('\\' | '"' | '\'')) => value.push(c),
+                    _ => {
+                        return Err(error_at(
+                            self.line,
+                            self.column - 1,
+                            "invalid escape sequence",
+                        ))
+                    }
+                },
+                Some('\n') | None => return Err(error_at(line, column, "unterminated terminal")),
+                Some(c) => value.push(c),
+            }
+        }
+
+        if value.is_empty() {
+            return Err(error_at(
+                line,
+                column,
+                "empty terminal, use %empty for an empty alternative",
+            ));
+        }
+        Ok(TokenKind::Quoted(value))
+    }
+
+    fn weight(&mut self, line: usize, column: usize) -> Result<TokenKind, GrammarError> {
+        let mut text = String::new();
+        loop {
+            match self.bump() {
+                Some(']') => break,
+                Some('\n') | None => return Err(error_at(line, column, "unterminated weight")),
+                Some(c) => text.push(c),
+            }
+        }
+
+        match text.trim().parse() {
+            Ok(weight) => Ok(TokenKind::Weight(weight)),
+            Err(_) => Err(error_at(line, column, "expected a number as the weight")),
+        }
+    }
+
+    fn bracketed(&mut self, line: usize, column: usize) -> Result<TokenKind, GrammarError> {
+        let mut name = String::new();
+        loop {
+            match self.bump() {
+                Some('>') => break,
+                Some('\\') => match self.bump() {
+                    Some('n') => name.push('\n'),
+                    Some('t') => name.push('\t'),
+                    Some(c @ ('\\' | '>')) => name.push(c),
+                    _ => {
+                        return Err(error_at(
+                            self.line,
+                            self.column - 1,
+                            "invalid escape sequence",
+                        ))
+                    }
+                },
+                Some('\n') | None => return Err(error_at(line, column, "unterminated name")),
+                Some(c) => name.push(c),
+            }
+        }
+
+        if name.is_empty() {
+            return Err(error_at(line, column, "empty name"));
+        }
+        Ok(TokenKind::Name(name))
+    }
+}
+
+fn tokenize(source: &str) -> Result<Vec<Token>, GrammarError> {
+    let mut lexer = Lexer {
+        chars: source.chars().peekable(),
+        line: 1,
+        column: 1,
+    };
+    let mut tokens = Vec::new();
+
+    while let Some(c) = lexer.peek() {
+        let (line, column) = (lexer.line, lexer.column);
+
+        let kind = match c {
+            _ if c.is_whitespace() => {
+                lexer.bump();
+                continue;
+            }
+            '#' => {
+                while lexer.peek().is_some_and(|c| c != '\n') {
+                    lexer.bump();
+                }
+                continue;
+            }
+            '|' | '(' | ')' | '?' | '*' | '+' | '>' => {
+                lexer.bump();
+                match c {
+                    '|' => TokenKind::Alt,
+                    '(' => TokenKind::Open,
+                    ')' => TokenKind::Close,
+                    '?' => TokenKind::Optional,
+                    '*' => TokenKind::Star,
+                    '+' => TokenKind::Plus,
+                    _ => TokenKind::Greater,
+                }
+            }
+            ':' => {
+                lexer.bump();
+                if lexer.bump() != Some(':') || lexer.bump() != Some('=') {
+                    return Err(error_at(line, column, "expected '::='"));
+                }
+                TokenKind::Define
+            }
+            '"' | '\'' => {
+                lexer.bump();
+                lexer.quoted(c, line, column)?
+            }
+            '<' => {
+                lexer.bump();
+                lexer.bracketed(line, column)?
+            }
+            '[' => {
+                lexer.bump();
+                lexer.weight(line, column)?
+            }
+            '%' => {
+                lexer.bump();
+                TokenKind::Directive(lexer.take_name())
+            }
+            _ if is_name_char(c) => TokenKind::Name(lexer.take_name()),
+            _ => {
+                return Err(error_at(
+                    line,
+                    column,
+                    &format!("unexpected character '{}'", c),
+                ))
+            }
+        };
+
+        tokens.push(Token { kind, line, column });
+    }
+
+    tokens.push(Token {
+        kind: TokenKind::End,
+        line: lexer.line,
+        column: lexer.column,
+    });
+
+    Ok(tokens)
+}
+
+// A disambiguation directive, applied once the grammar has been built
+enum Filter {
+    // Groups of rules in decreasing order of priority
+    Priority(Vec<Vec<Rule>>),
+    Associativity(Associativity, Vec<Rule>),
+    Reject(Vec<Rule>),
+    Prefer(Vec<Rule>),
+    Avoid(Vec<Rule>),
+}
+
+impl Filter {
+    fn apply(self, grammar: &mut Grammar) -> Result<(), GrammarError> {
+        match self {
+            Filter::Priority(groups) => {
+                for (i, higher) in groups.iter().enumerate() {
+                    for lower in groups[i + 1..].iter().flatten() {
+                        for rule in higher {
+                            grammar.add_priority(rule.clone(), lower.clone())?;
+                        }
+                    }
+                }
+                Ok(())
+            }
+            Filter::Associativity(associativity, rules) => {
+                grammar.add_associativity(associativity, rules)
+            }
+            Filter::Reject(rules) => rules
+                .into_iter()
+                .try_for_each(|rule| grammar.add_reject(rule)),
+            Filter::Prefer(rules) => rules
+                .into_iter()
+                .try_for_each(|rule| grammar.add_prefer(rule)),
+            Filter::Avoid(rules) => rules
+                .into_iter()
+                .try_for_each(|rule| grammar.add_avoid(rule)),
+        }
+    }
+}
+
+// The right side of a plain alternative, or None if it uses EBNF operators
+fn plain_right_side(expr: Expr) -> Option<Vec<Symbol>> {
+    match expr {
+        Expr::Symbol(symbol) => Some(vec![symbol]),
+        Expr::Seq(items) => items
+            .into_iter()
+            .map(|item| match item {
+                Expr::Symbol(symbol) => Some(symbol),
+                _ => None,
+            })
+            .collect(),
+        _ => None,
+    }
+}
+
+struct BnfParser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl BnfParser {
+    fn peek(&self) -> &Token {
+        &self.tokens[self.pos]
+    }
+
+    fn peek_kind(&self, offset: usize) -> &TokenKind {
+        // The token list always ends with End, so clamp to it
+        let i = (self.pos + offset).min(self.tokens.len() - 1);
+        &self.tokens[i].kind
+    }
+
+    fn next(&mut self) -> Token {
+        let token = self.tokens[self.pos].clone();
+        if self.pos < self.tokens.len() - 1 {
+            self.pos += 1;
+        }
+        token
+    }
+
+    fn error(&self, message: &str) -> GrammarError {
+        let token = self.peek();
+        error_at(token.line, token.column, message)
+    }
+
+    fn expect_name(&mut self) -> Result<String, GrammarError> {
+        match self.peek_kind(0).clone() {
+            TokenKind::Name(name) => {
+                self.next();
+                Ok(name)
+            }
+            _ => Err(self.error("expected a non-terminal name")),
+        }
+    }
+
+    // Whether the parser is at the start of the next rule, i.e. `name ::=`
+    fn at_rule_start(&self) -> bool {
+        matches!(self.peek_kind(0), TokenKind::Name(_)) && self.peek_kind(1) == &TokenKind::Define
+    }
+
+    // Parses alternatives separated by '|', up to the end of the rule or group
+    fn parse_alternatives(&mut self) -> Result<Expr, GrammarError> {
+        let mut alternatives = vec![self.parse_sequence()?];
+        while self.peek_kind(0) == &TokenKind::Alt {
+            self.next();
+            alternatives.push(self.parse_sequence()?);
+        }
+
+        if alternatives.len() == 1 {
+            Ok(alternatives.pop().unwrap())
+        } else {
+            Ok(Expr::Alt(alternatives))
+        }
+    }
+
+    // Parses the alternatives of a rule, which unlike those of a group can have weights
+    fn parse_rule_alternatives(
+        &mut self,
+        lhs: &Symbol,
+        weights: &mut Vec<(Token, Rule, f64)>,
+    ) -> Result<Expr, GrammarError> {
+        let mut alternatives = Vec::new();
+        loop {
+            let token = self.peek().clone();
+            let alternative = self.parse_sequence()?;
+            if let TokenKind::Weight(weight) = *self.peek_kind(0) {
+                let right_side = plain_right_side(alternative.clone()).ok_or_else(|| {
+                    error_at(
+                        token.line,
+                        token.column,
+                        "weights can only be given to alternatives without EBNF operators",
+                    )
+                })?;
+                weights.push((self.next(), (lhs.clone(), right_side), weight));
+            }
+            alternatives.push(alternative);
+
+            if self.peek_kind(0) != &TokenKind::Alt {
+                break;
+            }
+            self.next();
+        }
+
+        if alternatives.len() == 1 {
+            Ok(alternatives.pop().unwrap())
+        } else {
+            Ok(Expr::Alt(alternatives))
+        }
+    }
+
+    fn parse_sequence(&mut self) -> Result<Expr, GrammarError> {
+        let seq_token = self.peek().clone();
+        let mut items = Vec::new();
+        let mut empty = false;
+
+        loop {
+            if self.at_rule_start() {
+                break;
+            }
+            let mut item = match self.peek_kind(0).clone() {
+                TokenKind::Name(name) => Expr::Symbol(Symbol::NonTerminal(name)),
+                TokenKind::Quoted(value) => Expr::Symbol(Symbol::Terminal(value)),
+                TokenKind::Directive(d) if d == "empty" => {
+                    empty = true;
+                    self.next();
+                    continue;
+                }
+                TokenKind::Open => {
+                    self.next();
+                    let group = self.parse_alternatives()?;
+                    if self.peek_kind(0) != &TokenKind::Close {
+                        return Err(self.error("expected ')'"));
+                    }
+                    group
+                }
+                TokenKind::Define => return Err(self.error("unexpected '::='")),
+                TokenKind::Optional | TokenKind::Star | TokenKind::Plus => {
+                    return Err(self.error("operator does not follow a symbol or group"))
+                }
+                _ => break,
+            };
+            self.next();
+
+            // Apply any postfix operators
+            loop {
+                item = match self.peek_kind(0) {
+                    TokenKind::Optional => Expr::Optional(Box::new(item)),
+                    TokenKind::Star => Expr::Star(Box::new(item)),
+                    TokenKind::Plus => Expr::Plus(Box::new(item)),
+                    _ => break,
+                };
+                self.next();
+            }
+            items.push(item);
+        }
+
+        if empty && !items.is_empty() {
+            return Err(error_at(
+                seq_token.line,
+                seq_token.column,
+                "%empty cannot be combined with other symbols",
+            ));
+        }
+        if !empty && items.is_empty() {
+            return Err(self.error("expected a symbol, use %empty for an empty alternative"));
+        }
+
+        if items.len() == 1 {
+            Ok(items.pop().unwrap())
+        } else {
+            Ok(Expr::Seq(items))
+        }
+    }
+
+    // Parses `name ::= alternatives` naming plain rules, for a disambiguation directive
+    fn parse_rule_group(&mut self) -> Result<Vec<Rule>, GrammarError> {
+        let token = self.peek().clone();
+        let lhs = Symbol::NonTerminal(self.expect_name()?);
+        if self.peek_kind(0) != &TokenKind::Define {
+            return Err(self.error("expected '::='"));
+        }
+        self.next();
+
+        let alternatives = match self.parse_alternatives()? {
+            Expr::Alt(alternatives) => alternatives,
+            expr => vec![expr],
+        };
+        alternatives
+            .into_iter()
+            .map(|expr| {
+                let right_side = plain_right_side(expr).ok_or_else(|| {
+                    error_at(
+                        token.line,
+                        token.column,
+                        "disambiguation directives take rules without EBNF operators",
+                    )
+                })?;
+                Ok((lhs.clone(), right_side))
+            })
+            .collect()
+    }
+
+    fn parse_filter(&mut self, directive: &str) -> Result<Filter, GrammarError> {
+        Ok(match directive {
+            "priority" => {
+                let mut groups = vec![self.parse_rule_group()?];
+                while self.peek_kind(0) == &TokenKind::Greater {
+                    self.next();
+                    groups.push(self.parse_rule_group()?);
+                }
+                if groups.len() < 2 {
+                    return Err(self.error("expected '>'"));
+                }
+                Filter::Priority(groups)
+            }
+            "left" => Filter::Associativity(Associativity::Left, self.parse_rule_group()?),
+            "right" => Filter::Associativity(Associativity::Right, self.parse_rule_group()?),
+            "non-assoc" => Filter::Associativity(Associativity::NonAssoc, self.parse_rule_group()?),
+            "reject" => Filter::Reject(self.parse_rule_group()?),
+            "prefer" => Filter::Prefer(self.parse_rule_group()?),
+            _ => Filter::Avoid(self.parse_rule_group()?),
+        })
+    }
+
+    // Parses the symbols listed after a declaration directive
+    fn parse_declared(&mut self, terminals: bool) -> Vec<Symbol> {
+        let mut symbols = Vec::new();
+        loop {
+            if self.at_rule_start() {
+                break;
+            }
+            match self.peek_kind(0).clone() {
+                TokenKind::Name(name) if !terminals => symbols.push(Symbol::NonTerminal(name)),
+                TokenKind::Quoted(value) if terminals => symbols.push(Symbol::Terminal(value)),
+                _ => break,
+            }
+            self.next();
+        }
+        symbols
+    }
+
+    // Reads the grammar, along with the rules that were written more than once, which the
+    // grammar's set of rules can't show
+    fn parse_grammar(&mut self) -> Result<(Grammar, Vec<Rule>), GrammarError> {
+        let mut start: Option<String> = None;
+        // Rules are kept in order so that the first one can determine the start symbol
+        let mut rules: Vec<EbnfRule> = Vec::new();
+        let mut declared = Vec::new();
+        let mut hidden = HashSet::new();
+        let mut filters = Vec::new();
+        let mut weights = Vec::new();
+        let mut unnormalised = false;
+
+        loop {
+            match self.peek_kind(0).clone() {
+                TokenKind::End => break,
+                TokenKind::Directive(d) if d == "start" => {
+                    if start.is_some() {
+                        return Err(self.error("duplicate %start directive"));
+                    }
+                    self.next();
+                    start = Some(self.expect_name()?);
+                }
+                TokenKind::Directive(d) if d == "nonterminals" || d == "terminals" => {
+                    self.next();
+                    declared.extend(self.parse_declared(d == "terminals"));
+                }
+                TokenKind::Directive(d) if d == "unnormalised" => {
+                    self.next();
+                    unnormalised = true;
+                }
+                TokenKind::Directive(d) if d == "hidden" => {
+                    self.next();
+                    hidden.extend(self.parse_declared(false));
+                }
+                TokenKind::Directive(d)
+                    if matches!(
+                        d.as_str(),
+                        "priority" | "left" | "right" | "non-assoc" | "reject" | "prefer" | "avoid"
+                    ) =>
+                {
+                    let token = self.next();
+                    filters.push((token, self.parse_filter(&d)?));
+                }
+                TokenKind::Directive(d) => {
+                    return Err(self.error(&format!("unknown directive '%{}'", d)))
+                }
+                TokenKind::Name(_) => {
+                    let lhs = self.expect_name()?;
+                    if self.next().kind != TokenKind::Define {
+                        let token = &self.tokens[self.pos - 1];
+                        return Err(error_at(token.line, token.column, "expected '::='"));
+                    }
+                    let lhs = Symbol::NonTerminal(lhs);
+                    let alternatives = self.parse_rule_alternatives(&lhs, &mut weights)?;
+                    rules.push((lhs, alternatives));
+                }
+                _ => return Err(self.error("expected a rule or a directive")),
+            }
+        }
+
+        let start = match start.or_else(|| rules.first().map(|rule| name_of(&rule.0))) {
+            Some(start) => Symbol::NonTerminal(start),
+            None => return Err(self.error("grammar has no rules")),
+        };
+
+        // Terminals are worked out from quoting, everything else is a non-terminal
+        let mut non_terminals = HashSet::new();
+        let mut terminals = HashSet::new();
+        non_terminals.insert(start.clone());
+        for symbol in declared.into_iter().chain(hidden.iter().cloned()) {
+            collect_symbols(&Expr::Symbol(symbol), &mut non_terminals, &mut terminals);
+        }
+        for rule in &rules {
+            non_terminals.insert(rule.0.clone());
+            collect_symbols(&rule.1, &mut non_terminals, &mut terminals);
+        }
+
+        let mut written = HashSet::new();
+        let mut duplicates = Vec::new();
+        for (lhs, expr) in &rules {
+            let alternatives = match expr {
+                Expr::Alt(alternatives) => alternatives.clone(),
+                expr => vec![expr.clone()],
+            };
+            for right_side in alternatives.into_iter().filter_map(plain_right_side) {
+                let rule = (lhs.clone(), right_side);
+                if !written.insert(rule.clone()) {
+                    duplicates.push(rule);
+                }
+            }
+        }
+
+        let mut grammar = Grammar::from_ebnf(non_terminals, terminals, start, rules)?;
+        hidden.extend(grammar.get_hidden().iter().cloned());
+        grammar.set_hidden(hidden);
+
+        for (token, filter) in filters {
+            filter
+                .apply(&mut grammar)
+                .map_err(|error| error_at(token.line, token.column, &error.to_string()))?;
+        }
+
+        grammar.set_unnormalised(unnormalised);
+        for (token, rule, weight) in weights {
+            grammar
+                .set_weight(rule, weight)
+                .map_err(|error| error_at(token.line, token.column, &error.to_string()))?;
+        }
+        grammar.check_probabilities()?;
+
+        Ok((grammar, duplicates))
+    }
+}
+
+fn collect_symbols(
+    expr: &Expr,
+    non_terminals: &mut HashSet<Symbol>,
+    terminals: &mut HashSet<Symbol>,
+) {
+    match expr {
+        Expr::Symbol(symbol @ Symbol::NonTerminal(_)) => {
+            non_terminals.insert(symbol.clone());
+        }
+        Expr::Symbol(symbol @ Symbol::Terminal(_)) => {
+            terminals.insert(symbol.clone());
+        }
+        Expr::Seq(items) | Expr::Alt(items) => {
+            for item in items {
+                collect_symbols(item, non_terminals, terminals);
+            }
+        }
+        Expr::Optional(inner) | Expr::Star(inner) | Expr::Plus(inner) => {
+            collect_symbols(inner, non_terminals, terminals)
+        }
+    }
+}
+
+fn name_of(symbol: &Symbol) -> String {
+    match symbol {
+        Symbol::NonTerminal(name) | Symbol::Terminal(name) => name.clone(),
+    }
+}
+
+// Writes a name bare if the loader can read it back that way, otherwise in angle brackets. Empty
+// names can't be read back at all, which is why Grammar::new rejects them.
+fn write_name(f: &mut fmt::Formatter, name: &str) -> fmt::Result {
+    if !name.is_empty() && name.chars().all(is_name_char) {
+        return write!(f, "{}", name);
+    }
+
+    write!(f, "<")?;
+    for c in name.chars() {
+        match c {
+            '\n' => write!(f, "\\n")?,
+            '\t' => write!(f, "\\t")?,
+            '\\' | '>' => write!(f, "\\{}", c)?,
+            _ => write!(f, "{}", c)?,
+        }
+    }
+    write!(f, ">")
+}
+
+impl fmt::Display for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Symbol::NonTerminal(name) => write_name(f, name),
+            Symbol::Terminal(value) => {
+                write!(f, "\"")?;
+                for c in value.chars() {
+                    match c {
+                        '\n' => write!(f, "\\n")?,
+                        '\t' => write!(f, "\\t")?,
+                        '\\' | '"' => write!(f, "\\{}", c)?,
+                        _ => write!(f, "{}", c)?,
+                    }
+                }
+                write!(f, "\"")
+            }
+        }
+    }
+}
+
+fn right_side_text(right_side: &[Symbol]) -> String {
+    if right_side.is_empty() {
+        return " %empty".to_string();
+    }
+    right_side
+        .iter()
+        .map(|symbol| format!(" {}", symbol))
+        .collect()
+}
+
+// Formats a single rule in BNF, e.g. S ::= NP VP
+pub fn rule_to_bnf(rule: &Rule) -> String {
+    format!("{} ::={}", rule.0, right_side_text(&rule.1))
+}
+
+fn write_symbols<'a>(
+    f: &mut fmt::Formatter,
+    directive: &str,
+    symbols: impl IntoIterator<Item = &'a Symbol>,
+) -> fmt::Result {
+    let mut symbols: Vec<&Symbol> = symbols.into_iter().collect();
+    if symbols.is_empty() {
+        return Ok(());
+    }
+    symbols.sort();
+
+    write!(f, "%{}", directive)?;
+    for symbol in symbols {
+        write!(f, " {}", symbol)?;
+    }
+    writeln!(f)
+}
+
+// Prints the grammar as BNF that from_bnf_str reads back into an identical grammar. The rules of
+// the start symbol come first and the rest are grouped by left side in sorted order, with one
+// alternative per line.
+impl fmt::Display for Grammar {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut rules: Vec<&Rule> = self.get_all_rules().iter().collect();
+        rules.sort_by(|a, b| {
+            (a.0 != *self.get_start(), &a.0, &a.1).cmp(&(b.0 != *self.get_start(), &b.0, &b.1))
+        });
+
+        // Declare whatever the rules don't already mention
+        let mut used = HashSet::new();
+        used.insert(self.get_start());
+        for rule in &rules {
+            used.insert(&rule.0);
+            used.extend(rule.1.iter());
+        }
+
+        if rules.first().is_none_or(|rule| rule.0 != *self.get_start()) {
+            writeln!(f, "%start {}", self.get_start())?;
+        }
+        if self.is_unnormalised() {
+            writeln!(f, "%unnormalised")?;
+        }
+        write_symbols(
+            f,
+            "nonterminals",
+            self.get_non_terminals()
+                .iter()
+                .filter(|symbol| !used.contains(symbol) && !self.is_hidden(symbol)),
+        )?;
+        write_symbols(
+            f,
+            "terminals",
+            self.get_terminals()
+                .iter()
+                .filter(|symbol| !used.contains(symbol)),
+        )?;
+        write_symbols(f, "hidden", self.get_hidden())?;
+
+        let mut previous: Option<&Symbol> = None;
+        for rule in rules {
+            let lhs = rule.0.to_string();
+            if previous == Some(&rule.0) {
+                write!(f, "{:width$}|", "", width = lhs.chars().count() + 3)?;
+            } else {
+                write!(f, "{} ::=", lhs)?;
+            }
+            previous = Some(&rule.0);
+
+            write!(f, "{}", right_side_text(&rule.1))?;
+            if let Some(weight) = self.get_weights().get(rule) {
+                write!(f, " [{}]", weight)?;
+            }
+            writeln!(f)?;
+        }
+
+        let disambiguation = self.get_disambiguation();
+        for (higher, lower) in &disambiguation.priorities {
+            writeln!(
+                f,
+                "%priority {} > {}",
+                rule_to_bnf(higher),
+                rule_to_bnf(lower)
+            )?;
+        }
+        for (associativity, rules) in &disambiguation.associativity {
+            let directive = match associativity {
+                Associativity::Left => "left",
+                Associativity::Right => "right",
+                Associativity::NonAssoc => "non-assoc",
+            };
+            // The rules of a group share their left side
+            let mut rules = rules.iter();
+            if let Some(first) = rules.next() {
+                write!(f, "%{} {}", directive, rule_to_bnf(first))?;
+                for rule in rules {
+                    write!(f, " |{}", right_side_text(&rule.1))?;
+                }
+                writeln!(f)?;
+            }
+        }
+        for (directive, rules) in [
+            ("reject", &disambiguation.reject),
+            ("prefer", &disambiguation.prefer),
+            ("avoid", &disambiguation.avoid),
+        ] {
+            for rule in rules {
+                writeln!(f, "%{} {}", directive, rule_to_bnf(rule))?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Grammar {
+    // Renders the grammar as canonical BNF text, see the Display implementation
+    pub fn to_bnf(&self) -> String {
+        self.to_string()
+    }
+
+    // Reads a grammar from BNF text, see the top of this module for the notation
+    pub fn from_bnf_str(source: &str) -> Result<Grammar, GrammarError> {
+        let mut parser = BnfParser {
+            tokens: tokenize(source)?,
+            pos: 0,
+        };
+        Ok(parser.parse_grammar()?.0)
+    }
+
+    // Reads a grammar and lints it, also reporting the rules written more than once
+    pub fn lint_bnf_str(source: &str) -> Result<LintReport, GrammarError> {
+        let mut parser = BnfParser {
+            tokens: tokenize(source)?,
+            pos: 0,
+        };
+        let (grammar, duplicates) = parser.parse_grammar()?;
+
+        let mut report = grammar.lint();
+        report.add_duplicates(duplicates);
+        Ok(report)
+    }
+
+    // Reads a grammar from a file containing BNF text
+    pub fn from_bnf_file<P: AsRef<Path>>(path: P) -> Result<Grammar, GrammarError> {
+        let path = path.as_ref();
+        let source = fs::read_to_string(path).map_err(|error| GrammarError::Io {
+            path: path.to_path_buf(),
+            error,
+        })?;
+        Grammar::from_bnf_str(&source).map_err(|error| error.in_file(path))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use crate::error::GrammarError;
+    use crate::grammar::{Grammar, Symbol};
+
+    #[test]
+    fn awkward_names_round_trip() {
+        let a = Symbol::NonTerminal("a>b".to_string());
+        let c = Symbol::NonTerminal("c\\d\n".to_string());
+        let x = Symbol::Terminal("x".to_string());
+        let grammar = Grammar::new(
+            HashSet::from([a.clone(), c.clone()]),
+            HashSet::from([x.clone()]),
+            a.clone(),
+            HashSet::from([(a, vec![c.clone()]), (c, vec![x])]),
+        )
+        .unwrap();
+
+        let text = grammar.to_string();
+        assert_eq!(Grammar::from_bnf_str(&text).unwrap(), grammar);
+    }
+
+    #[test]
+    fn empty_names_are_rejected() {
+        let s = Symbol::NonTerminal("S".to_string());
+        let empty = Symbol::Terminal(String::new());
+        let result = Grammar::new(
+            HashSet::from([s.clone()]),
+            HashSet::from([empty.clone()]),
+            s.clone(),
+            HashSet::from([(s, vec![empty])]),
+        );
+
+        assert!(matches!(
+            result,
+            Err(GrammarError::EmptyName(Symbol::Terminal(_)))
+        ));
+    }
+}
@@ -0,0 +1,212 @@
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+use crate::error::GrammarError;
+use crate::grammar::{Grammar, Rule};
+use crate::symbols::{CompiledGrammar, RuleId};
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum Associativity {
+    Left,
+    Right,
+    NonAssoc,
+}
+
+// Declarative filters on the rules of a grammar, in the style of SDF. They are applied while the
+// parse forest is built, removing the derivations they rule out.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Disambiguation {
+    // Pairs of a higher and a lower priority rule. A node of the lower rule can't be a child of a
+    // node of the higher one. Priorities are transitive.
+    pub priorities: BTreeSet<(Rule, Rule)>,
+    // Groups of rules of one non-terminal that associate with each other. With left
+    // associativity a node of a rule in the group can't be the last child of a node of a rule in
+    // the group, with right associativity it can't be the first, and with neither it can be
+    // neither.
+    pub associativity: Vec<(Associativity, BTreeSet<Rule>)>,
+    // A non-terminal can't derive anything over a span that one of its reject rules derives
+    pub reject: BTreeSet<Rule>,
+    // Where a non-terminal derives a span with several rules, those that are preferred win, and
+    // those that are avoided lose to any other
+    pub prefer: BTreeSet<Rule>,
+    pub avoid: BTreeSet<Rule>,
+}
+
+impl Disambiguation {
+    pub fn is_empty(&self) -> bool {
+        self.priorities.is_empty()
+            && self.associativity.is_empty()
+            && self.reject.is_empty()
+            && self.prefer.is_empty()
+            && self.avoid.is_empty()
+    }
+}
+
+impl Grammar {
+    pub fn add_priority(&mut self, higher: Rule, lower: Rule) -> Result<(), GrammarError> {
+        self.check_rule(&higher)?;
+        self.check_rule(&lower)?;
+        self.disambiguation_mut().priorities.insert((higher, lower));
+        Ok(())
+    }
+
+    pub fn add_associativity(
+        &mut self,
+        associativity: Associativity,
+        rules: Vec<Rule>,
+    ) -> Result<(), GrammarError> {
+        for rule in &rules {
+            self.check_rule(rule)?;
+        }
+        if rules.iter().any(|rule| rule.0 != rules[0].0) {
+            return Err(GrammarError::MixedAssociativityGroup(rules));
+        }
+        self.disambiguation_mut()
+            .associativity
+            .push((associativity, rules.into_iter().collect()));
+        Ok(())
+    }
+
+    pub fn add_reject(&mut self, rule: Rule) -> Result<(), GrammarError> {
+        self.check_rule(&rule)?;
+        self.disambiguation_mut().reject.insert(rule);
+        Ok(())
+    }
+
+    pub fn add_prefer(&mut self, rule: Rule) -> Result<(), GrammarError> {
+        self.check_rule(&rule)?;
+        self.disambiguation_mut().prefer.insert(rule);
+        Ok(())
+    }
+
+    pub fn add_avoid(&mut self, rule: Rule) -> Result<(), GrammarError> {
+        self.check_rule(&rule)?;
+        self.disambiguation_mut().avoid.insert(rule);
+        Ok(())
+    }
+}
+
+// The filters of a grammar in terms of the ids of a compiled grammar, see Disambiguation
+#[derive(Debug, Clone, Default)]
+pub struct RuleFilters {
+    // (parent, position, child): a node of the child rule can't be the child of a node of the
+    // parent rule at that position of its right side
+    forbidden: HashSet<(RuleId, usize, RuleId)>,
+    // Indexed by RuleId
+    reject: Vec<bool>,
+    prefer: Vec<bool>,
+    avoid: Vec<bool>,
+}
+
+impl RuleFilters {
+    pub fn new(disambiguation: &Disambiguation, compiled: &CompiledGrammar) -> RuleFilters {
+        let ids: HashMap<Rule, RuleId> = (0..compiled.rules.len())
+            .map(|id| RuleId(id as u32))
+            .map(|id| (compiled.to_rule(id), id))
+            .collect();
+        let mut filters = RuleFilters::default();
+
+        // Close the priorities transitively
+        let mut lower: HashMap<RuleId, HashSet<RuleId>> = HashMap::new();
+        for (higher_rule, lower_rule) in &disambiguation.priorities {
+            lower
+                .entry(ids[higher_rule])
+                .or_default()
+                .insert(ids[lower_rule]);
+        }
+        loop {
+            let mut changed = false;
+            for higher in lower.keys().copied().collect::<Vec<_>>() {
+                let below: Vec<RuleId> = lower[&higher]
+                    .iter()
+                    .flat_map(|rule| lower.get(rule).into_iter().flatten())
+                    .copied()
+                    .collect();
+                for rule in below {
+                    changed |= lower.get_mut(&higher).unwrap().insert(rule);
+                }
+            }
+            if !changed {
+                break;
+            }
+        }
+
+        for (&higher, lower) in &lower {
+            let right_side = &compiled.rule(higher).rhs;
+            for &lower in lower {
+                for (position, &symbol) in right_side.iter().enumerate() {
+                    if symbol == compiled.rule(lower).lhs {
+                        filters.forbidden.insert((higher, position, lower));
+                    }
+                }
+            }
+        }
+
+        for (associativity, rules) in &disambiguation.associativity {
+            for parent in rules.iter().map(|rule| ids[rule]) {
+                let right_side = &compiled.rule(parent).rhs;
+                if right_side.is_empty() {
+                    continue;
+                }
+                let mut positions = Vec::new();
+                if *associativity != Associativity::Right {
+                    positions.push(right_side.len() - 1);
+                }
+                if *associativity != Associativity::Left {
+                    positions.push(0);
+                }
+
+                for child in rules.iter().map(|rule| ids[rule]) {
+                    for &position in &positions {
+                        if right_side.get(position) == Some(&compiled.rule(child).lhs) {
+                            filters.forbidden.insert((parent, position, child));
+                        }
+                    }
+                }
+            }
+        }
+
+        let flags = |rules: &BTreeSet<Rule>| {
+            let mut flags = vec![false; compiled.rules.len()];
+            for rule in rules {
+                flags[ids[rule].index()] = true;
+            }
+            flags
+        };
+        filters.reject = flags(&disambiguation.reject);
+        filters.prefer = flags(&disambiguation.prefer);
+        filters.avoid = flags(&disambiguation.avoid);
+
+        filters
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.forbidden.is_empty()
+            && !self
+                .reject
+                .iter()
+                .chain(&self.prefer)
+                .chain(&self.avoid)
+                .any(|&flag| flag)
+    }
+
+    pub fn allows_child(&self, parent: RuleId, position: usize, child: RuleId) -> bool {
+        !self.forbidden.contains(&(parent, position, child))
+    }
+
+    pub fn is_reject(&self, rule: RuleId) -> bool {
+        self.reject[rule.index()]
+    }
+
+    pub fn is_preferred(&self, rule: RuleId) -> bool {
+        self.prefer[rule.index()]
+    }
+
+    pub fn is_avoided(&self, rule: RuleId) -> bool {
+        self.avoid[rule.index()]
+    }
+}
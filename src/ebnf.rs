@@ -0,0 +1,173 @@
+use std::collections::HashSet;
+
+use crate::error::GrammarError;
+use crate::grammar::{Grammar, Rule, Symbol};
+
+// Right side of an EBNF rule. Lowering turns these into plain rules, inventing helper
+// non-terminals for repetitions and for groups of alternatives nested inside a sequence. The
+// helpers are marked as hidden in the grammar, so that trees show the structure that was written.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Symbol(Symbol),
+    // An empty sequence stands for the empty string
+    Seq(Vec<Expr>),
+    Alt(Vec<Expr>),
+    Optional(Box<Expr>),
+    Star(Box<Expr>),
+    Plus(Box<Expr>),
+}
+
+pub type EbnfRule = (Symbol, Expr);
+
+struct Lowering<'a> {
+    taken: &'a mut HashSet<Symbol>,
+    rules: Vec<Rule>,
+    helpers: Vec<Symbol>,
+}
+
+impl Lowering<'_> {
+    // Creates a helper non-terminal named after the rule it comes from, e.g. NP.1
+    fn new_helper(&mut self, lhs: &Symbol) -> Symbol {
+        let name = match lhs {
+            Symbol::NonTerminal(name) | Symbol::Terminal(name) => name,
+        };
+
+        let mut n = 1;
+        loop {
+            let helper = Symbol::NonTerminal(format!("{}.{}", name, n));
+            if self.taken.insert(helper.clone()) {
+                self.helpers.push(helper.clone());
+                return helper;
+            }
+            n += 1;
+        }
+    }
+
+    fn add_rules(&mut self, lhs: &Symbol, alternatives: Vec<Vec<Symbol>>) {
+        for right_side in alternatives {
+            let rule = (lhs.clone(), right_side);
+            if !self.rules.contains(&rule) {
+                self.rules.push(rule);
+            }
+        }
+    }
+
+    // Lowers an expression into the list of plain right sides it can stand for
+    fn lower(&mut self, lhs: &Symbol, expr: &Expr) -> Vec<Vec<Symbol>> {
+        match expr {
+            Expr::Symbol(symbol) => vec![vec![symbol.clone()]],
+            Expr::Seq(items) => {
+                let mut alternatives = vec![Vec::new()];
+                for item in items {
+                    let item_alternatives = self.lower_item(lhs, item);
+                    let mut combined = Vec::new();
+                    for prefix in &alternatives {
+                        for suffix in &item_alternatives {
+                            let mut right_side = prefix.clone();
+                            right_side.extend(suffix.iter().cloned());
+                            combined.push(right_side);
+                        }
+                    }
+                    alternatives = combined;
+                }
+                alternatives
+            }
+            Expr::Alt(alts) => {
+                let mut alternatives = Vec::new();
+                for alt in alts {
+                    for right_side in self.lower(lhs, alt) {
+                        if !alternatives.contains(&right_side) {
+                            alternatives.push(right_side);
+                        }
+                    }
+                }
+                alternatives
+            }
+            Expr::Optional(inner) => {
+                let mut alternatives = vec![Vec::new()];
+                alternatives.extend(self.lower(lhs, inner));
+                alternatives
+            }
+            Expr::Star(inner) => vec![vec![self.repetition(lhs, inner, true)]],
+            Expr::Plus(inner) => vec![vec![self.repetition(lhs, inner, false)]],
+        }
+    }
+
+    // Lowers an element of a sequence. Groups of several alternatives and optional elements get
+    // their own helper so that they don't multiply out with the rest of the sequence.
+    fn lower_item(&mut self, lhs: &Symbol, item: &Expr) -> Vec<Vec<Symbol>> {
+        let alternatives = self.lower(lhs, item);
+        if matches!(item, Expr::Alt(_) | Expr::Optional(_)) && alternatives.len() > 1 {
+            let helper = self.new_helper(lhs);
+            self.add_rules(&helper, alternatives);
+            vec![vec![helper]]
+        } else {
+            alternatives
+        }
+    }
+
+    // Creates a left-recursive helper matching one or more of the expression, H -> x | H x, or
+    // zero or more of it, H -> %empty | H x
+    fn repetition(&mut self, lhs: &Symbol, inner: &Expr, nullable: bool) -> Symbol {
+        let alternatives: Vec<Vec<Symbol>> = self
+            .lower(lhs, inner)
+            .into_iter()
+            .filter(|right_side| !right_side.is_empty())
+            .collect();
+
+        let helper = self.new_helper(lhs);
+        let mut rules = Vec::new();
+        if nullable {
+            rules.push(Vec::new());
+        }
+        for right_side in alternatives {
+            let mut recursive = vec![helper.clone()];
+            recursive.extend(right_side.iter().cloned());
+            if !nullable {
+                rules.push(right_side);
+            }
+            rules.push(recursive);
+        }
+        self.add_rules(&helper, rules);
+
+        helper
+    }
+}
+
+// Lowers EBNF rules into plain rules. Returns the rules along with the helper non-terminals
+// that had to be created, which are also added to the set of non-terminals.
+pub fn lower_rules(
+    rules: &[EbnfRule],
+    non_terminals: &mut HashSet<Symbol>,
+) -> (Vec<Rule>, Vec<Symbol>) {
+    let mut lowering = Lowering {
+        taken: non_terminals,
+        rules: Vec::new(),
+        helpers: Vec::new(),
+    };
+
+    for (lhs, expr) in rules {
+        let alternatives = lowering.lower(lhs, expr);
+        lowering.add_rules(lhs, alternatives);
+    }
+
+    (lowering.rules, lowering.helpers)
+}
+
+impl Grammar {
+    // Creates a grammar from EBNF rules, hiding the generated helper non-terminals
+    pub fn from_ebnf(
+        mut non_terminals: HashSet<Symbol>,
+        terminals: HashSet<Symbol>,
+        start: Symbol,
+        rules: Vec<EbnfRule>,
+    ) -> Result<Grammar, GrammarError> {
+        let (rules, helpers) = lower_rules(&rules, &mut non_terminals);
+
+        let mut grammar =
+            Grammar::new(non_terminals, terminals, start, rules.into_iter().collect())?;
+        grammar.set_hidden(helpers.into_iter().collect());
+
+        Ok(grammar)
+    }
+}
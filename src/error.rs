@@ -0,0 +1,239 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::bnf::rule_to_bnf;
+use crate::grammar::{Rule, Symbol};
+
+#[derive(Debug)]
+pub enum GrammarError {
+    // The start symbol is not in the set of non-terminals
+    StartNotNonTerminal(Symbol),
+    // The left side of the rule is not in the set of non-terminals
+    LhsNotNonTerminal(Rule),
+    // The left side of the rule is a terminal
+    LhsIsTerminal(Rule),
+    // A symbol on the right side of the rule is neither a terminal nor a non-terminal
+    UndeclaredSymbol {
+        symbol: Symbol,
+        rule: Rule,
+    },
+    // A declared symbol has an empty name, which BNF text can't represent
+    EmptyName(Symbol),
+    // Malformed BNF or treebank text, with the file it was read from if any
+    Syntax {
+        path: Option<PathBuf>,
+        line: usize,
+        column: usize,
+        message: String,
+    },
+    // A rule weight is negative, infinite or NaN
+    InvalidWeight {
+        rule: Rule,
+        weight: f64,
+    },
+    // The weights of the rules of a non-terminal don't sum to 1, in a grammar that isn't
+    // allowed to be unnormalised
+    NotNormalised {
+        non_terminal: Symbol,
+        sum: f64,
+    },
+    // A disambiguation filter or weight names a rule that is not in the grammar
+    UnknownRule(Rule),
+    // The rules of an associativity group don't all have the same left side
+    MixedAssociativityGroup(Vec<Rule>),
+    // A grammar can't be read off a treebank without trees
+    EmptyTreebank,
+    // A grammar or treebank file could not be read
+    Io {
+        path: PathBuf,
+        error: io::Error,
+    },
+}
+
+impl fmt::Display for GrammarError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            GrammarError::StartNotNonTerminal(start) => write!(
+                f,
+                "starting symbol {} is not in the set of non-terminals",
+                start
+            ),
+            GrammarError::LhsNotNonTerminal(rule) => write!(
+                f,
+                "left side of rule {} is not a non-terminal",
+                rule_to_bnf(rule)
+            ),
+            GrammarError::LhsIsTerminal(rule) => {
+                write!(f, "left side of rule {} is a terminal", rule_to_bnf(rule))
+            }
+            GrammarError::UndeclaredSymbol { symbol, rule } => write!(
+                f,
+                "symbol {} on the right side of rule {} is neither a terminal, nor a non-terminal",
+                symbol,
+                rule_to_bnf(rule)
+            ),
+            GrammarError::EmptyName(symbol) => match symbol {
+                Symbol::NonTerminal(_) => write!(f, "a non-terminal has an empty name"),
+                Symbol::Terminal(_) => write!(f, "a terminal has an empty name"),
+            },
+            GrammarError::Syntax {
+                path,
+                line,
+                column,
+                message,
+            } => {
+                if let Some(path) = path {
+                    write!(f, "{}: ", path.display())?;
+                }
+                write!(f, "line {}, column {}: {}", line, column, message)
+            }
+            GrammarError::InvalidWeight { rule, weight } => write!(
+                f,
+                "weight {} of rule {} is not a finite, non-negative number",
+                weight,
+                rule_to_bnf(rule)
+            ),
+            GrammarError::NotNormalised { non_terminal, sum } => write!(
+                f,
+                "weights of the rules of {} sum to {} rather than 1",
+                non_terminal, sum
+            ),
+            GrammarError::UnknownRule(rule) => {
+                write!(f, "rule {} is not in the grammar", rule_to_bnf(rule))
+            }
+            GrammarError::MixedAssociativityGroup(rules) => {
+                let rules: Vec<String> = rules.iter().map(rule_to_bnf).collect();
+                write!(
+                    f,
+                    "rules of an associativity group have different left sides: {}",
+                    rules.join(", ")
+                )
+            }
+            GrammarError::EmptyTreebank => write!(f, "the treebank has no trees"),
+            GrammarError::Io { path, error } => {
+                write!(f, "could not read {}: {}", path.display(), error)
+            }
+        }
+    }
+}
+
+impl GrammarError {
+    // Names the file a syntax error was found in
+    pub(crate) fn in_file(self, file: &Path) -> GrammarError {
+        match self {
+            GrammarError::Syntax {
+                line,
+                column,
+                message,
+                ..
+            } => GrammarError::Syntax {
+                path: Some(file.to_path_buf()),
+                line,
+                column,
+                message,
+            },
+            error => error,
+        }
+    }
+}
+
+impl Error for GrammarError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            GrammarError::Io { error, .. } => Some(error),
+            _ => None,
+        }
+    }
+}
+
+// Every problem found while validating a grammar, see Grammar::new_validated
+#[derive(Debug)]
+pub struct ValidationReport {
+    pub errors: Vec<GrammarError>,
+}
+
+impl fmt::Display for ValidationReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} problem(s) found in the grammar", self.errors.len())?;
+        for error in &self.errors {
+            write!(f, "\n  {}", error)?;
+        }
+        Ok(())
+    }
+}
+
+impl Error for ValidationReport {}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseError {
+    // A privileged symbol is not in the grammar's set of non-terminals
+    PrivilegedNotNonTerminal(Symbol),
+    // The grammar has no rule for its start symbol
+    NoStartingRule(Symbol),
+    // The input word at `index` is not one of the grammar's terminals
+    UnknownTerminal {
+        index: usize,
+        terminal: Symbol,
+    },
+    // The input is not in the language. Parsing got as far as `position`, where `found` is the
+    // input terminal (or None at the end of the input) and `expected` the terminals that could
+    // have continued a parse.
+    NoParse {
+        position: usize,
+        found: Option<Symbol>,
+        expected: Vec<Symbol>,
+    },
+    // The input is in the language, but the grammar's disambiguation filters removed every parse
+    Filtered,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParseError::PrivilegedNotNonTerminal(symbol) => write!(
+                f,
+                "privileged symbol {} is not in the set of non-terminals",
+                symbol
+            ),
+            ParseError::NoStartingRule(start) => {
+                write!(f, "no rule reducing the starting symbol {}", start)
+            }
+            ParseError::UnknownTerminal { index, terminal } => write!(
+                f,
+                "input symbol {} at position {} is not one of the grammar's terminals",
+                terminal, index
+            ),
+            ParseError::NoParse {
+                position,
+                found,
+                expected,
+            } => {
+                match found {
+                    Some(terminal) => write!(
+                        f,
+                        "no parse: unexpected {} at position {}",
+                        terminal, position
+                    )?,
+                    None => write!(
+                        f,
+                        "no parse: unexpected end of input at position {}",
+                        position
+                    )?,
+                }
+                if !expected.is_empty() {
+                    let expected: Vec<String> = expected.iter().map(|t| t.to_string()).collect();
+                    write!(f, ", expected one of {}", expected.join(", "))?;
+                }
+                Ok(())
+            }
+            ParseError::Filtered => write!(
+                f,
+                "every parse was removed by the grammar's disambiguation filters"
+            ),
+        }
+    }
+}
+
+impl Error for ParseError {}
@@ -0,0 +1,214 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::grammar::Symbol;
+use crate::parser::Parser;
+use crate::tree::ParseTree;
+
+// PARSEVAL evaluation of parses against gold trees. A tree is compared as the brackets it is
+// made of, each a label with the span it covers. The pre-terminals above single words are left
+// out, as tagging is scored separately.
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Evaluation {
+    pub sentences: usize,
+    // Sentences the parser found no parse for, which count as parses without brackets
+    pub unparsed: usize,
+    pub gold_brackets: usize,
+    pub test_brackets: usize,
+    // Brackets of the parses with the same label and span as a bracket of the gold tree
+    pub matched_brackets: usize,
+    // Sentences whose parse has exactly the brackets of the gold tree
+    pub exact_matches: usize,
+    // Brackets of the parses that overlap a bracket of the gold tree without either being
+    // inside the other
+    pub crossing_brackets: usize,
+    // Sentences whose parse has no crossing brackets
+    pub no_crossing: usize,
+}
+
+type Brackets<'t> = HashMap<(&'t Symbol, (usize, usize)), usize>;
+
+fn add_brackets<'t>(tree: &'t ParseTree, brackets: &mut Brackets<'t>) {
+    if tree.is_leaf() || matches!(&tree.children[..], [child] if child.is_leaf()) {
+        return;
+    }
+    *brackets.entry((&tree.label, tree.span)).or_default() += 1;
+    for child in &tree.children {
+        add_brackets(child, brackets);
+    }
+}
+
+fn crosses(a: (usize, usize), b: (usize, usize)) -> bool {
+    (a.0 < b.0 && b.0 < a.1 && a.1 < b.1) || (b.0 < a.0 && a.0 < b.1 && b.1 < a.1)
+}
+
+fn ratio(part: usize, whole: usize) -> f64 {
+    if whole == 0 {
+        0.0
+    } else {
+        part as f64 / whole as f64
+    }
+}
+
+impl Evaluation {
+    pub fn new() -> Evaluation {
+        Evaluation::default()
+    }
+
+    // Scores the parse of a sentence, None if it couldn't be parsed, against its gold tree
+    pub fn add(&mut self, gold: &ParseTree, test: Option<&ParseTree>) {
+        let mut gold_brackets = Brackets::new();
+        add_brackets(gold, &mut gold_brackets);
+        let mut test_brackets = Brackets::new();
+        if let Some(test) = test {
+            add_brackets(test, &mut test_brackets);
+        }
+
+        self.sentences += 1;
+        self.unparsed += usize::from(test.is_none());
+        self.gold_brackets += gold_brackets.values().sum::<usize>();
+        self.test_brackets += test_brackets.values().sum::<usize>();
+        self.matched_brackets += test_brackets
+            .iter()
+            .map(|(bracket, &count)| count.min(gold_brackets.get(bracket).copied().unwrap_or(0)))
+            .sum::<usize>();
+        self.exact_matches += usize::from(test.is_some() && gold_brackets == test_brackets);
+
+        let crossing: usize = test_brackets
+            .iter()
+            .filter(|((_, span), _)| {
+                gold_brackets
+                    .keys()
+                    .any(|&(_, gold_span)| crosses(*span, gold_span))
+            })
+            .map(|(_, &count)| count)
+            .sum();
+        self.crossing_brackets += crossing;
+        self.no_crossing += usize::from(test.is_some() && crossing == 0);
+    }
+
+    // Labelled precision, the share of the parses' brackets that are in the gold trees
+    pub fn precision(&self) -> f64 {
+        ratio(self.matched_brackets, self.test_brackets)
+    }
+
+    // Labelled recall, the share of the gold trees' brackets that are in the parses
+    pub fn recall(&self) -> f64 {
+        ratio(self.matched_brackets, self.gold_brackets)
+    }
+
+    pub fn f1(&self) -> f64 {
+        let (precision, recall) = (self.precision(), self.recall());
+        if precision + recall == 0.0 {
+            0.0
+        } else {
+            2.0 * precision * recall / (precision + recall)
+        }
+    }
+
+    // The share of sentences parsed exactly as in the gold trees
+    pub fn exact_match(&self) -> f64 {
+        ratio(self.exact_matches, self.sentences)
+    }
+
+    pub fn average_crossing(&self) -> f64 {
+        ratio(self.crossing_brackets, self.sentences)
+    }
+}
+
+// Scores a parse from the child of its root if the root is the symbol added over the roots of
+// the gold trees, like the TOP symbol of Grammar::from_treebank
+fn strip_added_root(test: ParseTree, added_root: Option<&Symbol>) -> ParseTree {
+    if added_root != Some(&test.label) || test.children.len() != 1 || test.children[0].is_leaf() {
+        return test;
+    }
+    test.children.into_iter().next().unwrap()
+}
+
+impl Parser<'_> {
+    // Parses the words of each gold tree, scoring the most probable parse against the tree.
+    // `added_root` is a start symbol the grammar puts over the gold roots, which isn't scored.
+    pub fn evaluate(&mut self, gold: &[ParseTree], added_root: Option<&Symbol>) -> Evaluation {
+        let mut evaluation = Evaluation::new();
+        for tree in gold {
+            let words = tree
+                .leaves()
+                .into_iter()
+                .map(|leaf| match leaf {
+                    Symbol::NonTerminal(word) | Symbol::Terminal(word) => word.as_str(),
+                })
+                .collect();
+            let test = self
+                .parse_best(words)
+                .ok()
+                .map(|(test, _)| strip_added_root(test, added_root));
+            evaluation.add(tree, test.as_ref());
+        }
+        evaluation
+    }
+}
+
+impl fmt::Display for Evaluation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "{} sentence(s), {} without a parse",
+            self.sentences, self.unparsed
+        )?;
+        writeln!(
+            f,
+            "labelled precision  {:6.2}%  ({} of {} brackets)",
+            100.0 * self.precision(),
+            self.matched_brackets,
+            self.test_brackets
+        )?;
+        writeln!(
+            f,
+            "labelled recall     {:6.2}%  ({} of {} brackets)",
+            100.0 * self.recall(),
+            self.matched_brackets,
+            self.gold_brackets
+        )?;
+        writeln!(f, "labelled F1         {:6.2}%", 100.0 * self.f1())?;
+        writeln!(
+            f,
+            "exact match         {:6.2}%  ({} sentence(s))",
+            100.0 * self.exact_match(),
+            self.exact_matches
+        )?;
+        write!(
+            f,
+            "crossing brackets   {:6.2} per sentence, {} sentence(s) with none",
+            self.average_crossing(),
+            self.no_crossing
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::grammar::Grammar;
+    use crate::treebank::read_treebank;
+
+    #[test]
+    fn treebank_grammar_scores_perfectly_on_its_treebank() {
+        let gold = read_treebank(
+            "(S (NP (N they)) (VP (V fish)))
+             (FRAG (NP (N fish)) (PP (P in) (NP (N rivers))))",
+        )
+        .unwrap();
+        let treebank = Grammar::from_treebank(&gold).unwrap();
+        let mut parser = treebank.grammar.get_parser(treebank.privileged).unwrap();
+
+        let evaluation = parser.evaluate(&gold, treebank.added_root.as_ref());
+
+        assert_eq!(evaluation.exact_matches, 2);
+        assert_eq!(evaluation.matched_brackets, evaluation.test_brackets);
+        assert_eq!(evaluation.matched_brackets, evaluation.gold_brackets);
+
+        // Without naming the added root, its bracket counts against every parse
+        let evaluation = parser.evaluate(&gold, None);
+        assert_eq!(evaluation.exact_matches, 0);
+    }
+}
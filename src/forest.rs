@@ -0,0 +1,551 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+
+use crate::grammar::{Rule, Symbol};
+use crate::kbest::Ranking;
+use crate::symbols::{CompiledGrammar, RuleId, SymbolId};
+use crate::tree::ParseTree;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct NodeId(pub usize);
+
+// What the last symbol of a packed node was matched with: the complete node of a non-terminal,
+// or the terminal at a position of the input
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Child {
+    Node(NodeId),
+    Terminal(usize),
+}
+
+// One derivation of a node. `left` derives the rule's symbols before the last one the node
+// covers and is None when there are none, `right` derives that last symbol and is None only for
+// an empty rule.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Packed {
+    pub left: Option<NodeId>,
+    pub right: Option<Child>,
+}
+
+// A rule recognised up to `dot` over a span of the input. Complete nodes, with the dot at the end
+// of the rule, stand for the rule's left side; the others for a prefix of its right side.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Node {
+    pub rule: RuleId,
+    pub dot: usize,
+    pub span: (usize, usize),
+    // Every way of deriving the node, at least one
+    pub packed: Vec<Packed>,
+}
+
+// The number of parses of an input. Counts saturate, so Finite(u64::MAX) stands for that many or
+// more. A cyclic grammar can derive the input in infinitely many ways, though only the
+// derivations that don't go round a cycle are given by Forest::get_trees.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ParseCount {
+    Finite(u64),
+    Infinite,
+}
+
+impl fmt::Display for ParseCount {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParseCount::Finite(u64::MAX) => write!(f, "at least {}", u64::MAX),
+            ParseCount::Finite(count) => write!(f, "{}", count),
+            ParseCount::Infinite => write!(f, "infinitely many"),
+        }
+    }
+}
+
+// A shared packed parse forest holding every parse of an input. Nodes are shared between the
+// derivations they appear in, and a node with more than one packed derivation is ambiguous. The
+// forest is binarised: a packed node has at most two children, the left one being the rest of
+// the rule, so the forest stays polynomial in the length of the input.
+#[derive(Debug, Clone)]
+pub struct Forest {
+    grammar: Rc<CompiledGrammar>,
+    input: Vec<SymbolId>,
+    nodes: Vec<Node>,
+    // Complete nodes of the start symbol spanning the whole input
+    roots: Vec<NodeId>,
+    // Complete nodes by the left side of their rule and span
+    symbol_nodes: HashMap<(SymbolId, (usize, usize)), Vec<NodeId>>,
+}
+
+impl Forest {
+    pub(crate) fn new(
+        grammar: Rc<CompiledGrammar>,
+        input: Vec<SymbolId>,
+        nodes: Vec<Node>,
+        roots: Vec<NodeId>,
+    ) -> Forest {
+        let mut symbol_nodes: HashMap<_, Vec<NodeId>> = HashMap::new();
+        for (id, node) in nodes.iter().enumerate() {
+            let rule = grammar.rule(node.rule);
+            if node.dot == rule.rhs.len() {
+                symbol_nodes
+                    .entry((rule.lhs, node.span))
+                    .or_default()
+                    .push(NodeId(id));
+            }
+        }
+
+        Forest {
+            grammar,
+            input,
+            nodes,
+            roots,
+            symbol_nodes,
+        }
+    }
+
+    pub fn get_roots(&self) -> &[NodeId] {
+        &self.roots
+    }
+
+    pub fn get_node(&self, id: NodeId) -> &Node {
+        &self.nodes[id.0]
+    }
+
+    pub fn get_nodes(&self) -> &[Node] {
+        &self.nodes
+    }
+
+    pub fn get_grammar(&self) -> &CompiledGrammar {
+        &self.grammar
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    // The packed derivations of the node
+    pub fn get_alternatives(&self, id: NodeId) -> &[Packed] {
+        &self.get_node(id).packed
+    }
+
+    pub fn is_ambiguous(&self, id: NodeId) -> bool {
+        self.get_alternatives(id).len() > 1
+    }
+
+    pub fn is_complete(&self, id: NodeId) -> bool {
+        let node = self.get_node(id);
+        node.dot == self.grammar.rule(node.rule).rhs.len()
+    }
+
+    pub fn get_rule(&self, id: NodeId) -> Rule {
+        self.grammar.to_rule(self.get_node(id).rule)
+    }
+
+    // The left side of the node's rule
+    pub fn get_symbol(&self, id: NodeId) -> &Symbol {
+        let lhs = self.grammar.rule(self.get_node(id).rule).lhs;
+        self.grammar.symbols.symbol(lhs)
+    }
+
+    // The input terminal at the position
+    pub fn get_terminal(&self, position: usize) -> &Symbol {
+        self.grammar.symbols.symbol(self.input[position])
+    }
+
+    // The complete nodes deriving the non-terminal over the span, one for each rule it was
+    // derived with
+    pub fn get_symbol_nodes(&self, symbol: &Symbol, span: (usize, usize)) -> &[NodeId] {
+        self.grammar
+            .symbols
+            .id(symbol)
+            .and_then(|id| self.symbol_nodes.get(&(id, span)))
+            .map_or(&[], Vec::as_slice)
+    }
+
+    // The children of one derivation of a node, from left to right. A packed node only holds the
+    // last child, so the rest are found by following the left nodes, taking the first of their
+    // derivations.
+    pub fn get_children(&self, packed: &Packed) -> Vec<Child> {
+        let mut children = Vec::new();
+        children.extend(packed.right);
+
+        let mut left = packed.left;
+        while let Some(id) = left {
+            let first = &self.get_alternatives(id)[0];
+            children.extend(first.right);
+            left = first.left;
+        }
+
+        children.reverse();
+        children
+    }
+
+    // Builds the tree of the node's first derivation, taking the first packed node at every
+    // node below it too
+    pub fn get_tree(&self, id: NodeId) -> ParseTree {
+        let mut choices = Vec::new();
+        TreeBuilder::new(self, Choices::sequence(&mut choices))
+            .build_tree(id)
+            .expect("the first derivation of a node is never cyclic")
+    }
+
+    // Builds the tree of the node taking, at every node below it, the packed node given by
+    // `chosen` (indexed by NodeId). Returns None if that goes round a cycle.
+    pub(crate) fn build_chosen_tree(&self, id: NodeId, chosen: &[usize]) -> Option<ParseTree> {
+        TreeBuilder::new(self, Choices::Fixed(chosen)).build_tree(id)
+    }
+
+    // Builds the tree of the derivation of the node with the given rank, see kbest
+    pub(crate) fn build_ranked_tree(
+        &self,
+        id: NodeId,
+        rank: usize,
+        ranking: &Ranking,
+    ) -> Option<ParseTree> {
+        let pending = HashMap::from([(id, vec![rank])]);
+        TreeBuilder::new(self, Choices::Ranked { ranking, pending }).build_tree(id)
+    }
+
+    // Returns an iterator over the trees of every parse, stopping after `limit` trees if given.
+    // Trees are built one at a time as the iterator is advanced. Derivations going round a cycle
+    // of the grammar are left out, so there are finitely many.
+    pub fn get_trees(&self, limit: Option<usize>) -> Trees<'_> {
+        Trees {
+            forest: self,
+            root: 0,
+            choices: Vec::new(),
+            remaining: limit,
+        }
+    }
+
+    // Counts the parses without building any trees, by adding up the derivations of each node
+    // once
+    pub fn count_parses(&self) -> ParseCount {
+        let mut counts = vec![None; self.nodes.len()];
+        let mut total = 0u64;
+        for &root in &self.roots {
+            match self.count_node(root, &mut counts) {
+                Some(count) => total = total.saturating_add(count),
+                None => return ParseCount::Infinite,
+            }
+        }
+        ParseCount::Finite(total)
+    }
+
+    // Returns None if the node's derivations go round a cycle. `counts` holds Some(None) for the
+    // nodes currently being counted, so that coming back to one of them shows the cycle.
+    fn count_node(&self, id: NodeId, counts: &mut Vec<Option<Option<u64>>>) -> Option<u64> {
+        if let Some(count) = counts[id.0] {
+            return count;
+        }
+        counts[id.0] = Some(None);
+
+        let mut count = 0u64;
+        for packed in self.get_alternatives(id) {
+            let left = match packed.left {
+                Some(left) => self.count_node(left, counts)?,
+                None => 1,
+            };
+            let right = match packed.right {
+                Some(Child::Node(child)) => self.count_node(child, counts)?,
+                _ => 1,
+            };
+            count = count.saturating_add(left.saturating_mul(right));
+        }
+
+        counts[id.0] = Some(Some(count));
+        Some(count)
+    }
+}
+
+// Iterator over the trees of a forest, see Forest::get_trees
+pub struct Trees<'f> {
+    forest: &'f Forest,
+    // Index of the root whose trees are being built
+    root: usize,
+    // The packed node chosen at each ambiguous node met while building the last tree, and how
+    // many there were to choose from. Counting these up like an odometer walks through every
+    // combination.
+    choices: Vec<(usize, usize)>,
+    remaining: Option<usize>,
+}
+
+impl Iterator for Trees<'_> {
+    type Item = ParseTree;
+
+    fn next(&mut self) -> Option<ParseTree> {
+        while self.remaining != Some(0) {
+            let &root = self.forest.get_roots().get(self.root)?;
+            let choices = Choices::sequence(&mut self.choices);
+            let tree = TreeBuilder::new(self.forest, choices).build_tree(root);
+
+            // Move on to the next combination of choices: the last choice that has alternatives
+            // left is advanced and the ones after it start again from the first
+            loop {
+                match self.choices.pop() {
+                    Some((chosen, count)) if chosen + 1 < count => {
+                        self.choices.push((chosen + 1, count));
+                        break;
+                    }
+                    Some(_) => {}
+                    None => {
+                        self.root += 1;
+                        break;
+                    }
+                }
+            }
+
+            if let Some(tree) = tree {
+                if let Some(remaining) = &mut self.remaining {
+                    *remaining -= 1;
+                }
+                return Some(tree);
+            }
+        }
+
+        None
+    }
+}
+
+// How a TreeBuilder picks the packed node of an ambiguous node
+enum Choices<'c> {
+    // Follows a sequence of choices, one for each ambiguous node met, extending it with the
+    // first packed node wherever it runs out
+    Sequence {
+        choices: &'c mut Vec<(usize, usize)>,
+        // The next choice to follow
+        cursor: usize,
+    },
+    // The same packed node wherever a node is met, indexed by NodeId
+    Fixed(&'c [usize]),
+    // The ranked derivations of the nodes. Choosing a node's derivation fixes the ranks of its
+    // children, which are pending until the children are met. The children of a node are met in
+    // the reverse of the order they are chosen in, so the ranks of each node are a stack.
+    Ranked {
+        ranking: &'c Ranking,
+        pending: HashMap<NodeId, Vec<usize>>,
+    },
+}
+
+impl<'c> Choices<'c> {
+    fn sequence(choices: &'c mut Vec<(usize, usize)>) -> Choices<'c> {
+        Choices::Sequence { choices, cursor: 0 }
+    }
+}
+
+// Builds one tree of a forest
+struct TreeBuilder<'f, 'c> {
+    forest: &'f Forest,
+    choices: Choices<'c>,
+    // Complete nodes on the way down from the root, to spot cyclic derivations
+    path: Vec<NodeId>,
+}
+
+impl<'f, 'c> TreeBuilder<'f, 'c> {
+    fn new(forest: &'f Forest, choices: Choices<'c>) -> TreeBuilder<'f, 'c> {
+        TreeBuilder {
+            forest,
+            choices,
+            path: Vec::new(),
+        }
+    }
+
+    fn choose(&mut self, id: NodeId) -> &'f Packed {
+        let alternatives = self.forest.get_alternatives(id);
+        match &mut self.choices {
+            // Even a node with one packed node has ranked derivations, for its children's ranks
+            Choices::Ranked { ranking, pending } => {
+                let rank = pending.get_mut(&id).and_then(Vec::pop).unwrap_or(0);
+                let derivation = ranking.get_derivation(id, rank);
+                let packed = &alternatives[derivation.packed];
+                if let Some(left) = packed.left {
+                    pending.entry(left).or_default().push(derivation.left);
+                }
+                if let Some(Child::Node(right)) = packed.right {
+                    pending.entry(right).or_default().push(derivation.right);
+                }
+                packed
+            }
+            _ if alternatives.len() == 1 => &alternatives[0],
+            Choices::Sequence { choices, cursor } => {
+                if *cursor == choices.len() {
+                    choices.push((0, alternatives.len()));
+                }
+                let (chosen, _) = choices[*cursor];
+                *cursor += 1;
+                &alternatives[chosen]
+            }
+            Choices::Fixed(chosen) => &alternatives[chosen[id.0]],
+        }
+    }
+
+    // Returns None if the derivation goes round a cycle
+    fn build_tree(&mut self, id: NodeId) -> Option<ParseTree> {
+        let children = self.build_children(id)?;
+        let label = self.forest.get_symbol(id).clone();
+        let right_side = children.iter().map(|child| child.label.clone()).collect();
+
+        Some(ParseTree {
+            rule: Some((label.clone(), right_side)),
+            label,
+            span: self.forest.get_node(id).span,
+            children,
+        })
+    }
+
+    // Builds the subtrees for the right side of the node's rule, splicing in the children of any
+    // hidden helper non-terminals
+    fn build_children(&mut self, id: NodeId) -> Option<Vec<ParseTree>> {
+        if self.path.contains(&id) {
+            return None;
+        }
+        self.path.push(id);
+
+        // Follow the left nodes back to the start of the rule, collecting the last child of each
+        let mut last_children = Vec::new();
+        let mut packed = self.choose(id);
+        loop {
+            last_children.extend(packed.right);
+            match packed.left {
+                Some(left) => packed = self.choose(left),
+                None => break,
+            }
+        }
+
+        let mut children = Vec::new();
+        for child in last_children.into_iter().rev() {
+            match child {
+                Child::Node(child) => {
+                    let lhs = self
+                        .forest
+                        .grammar
+                        .rule(self.forest.get_node(child).rule)
+                        .lhs;
+                    if self.forest.grammar.is_hidden(lhs) {
+                        children.extend(self.build_children(child)?);
+                    } else {
+                        children.push(self.build_tree(child)?);
+                    }
+                }
+                Child::Terminal(position) => {
+                    let terminal = self.forest.get_terminal(position).clone();
+                    children.push(ParseTree::leaf(terminal, position));
+                }
+            }
+        }
+
+        self.path.pop();
+        Some(children)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::{Forest, ParseCount};
+    use crate::grammar::{Grammar, Symbol};
+
+    // The grammar of the demo in main.rs
+    const DEMO: &str = r#"
+        S  ::= NP VP
+        NP ::= N PP | N
+        PP ::= P NP
+        VP ::= VP PP | V VP | V NP | V
+        N  ::= "can" | "fish" | "rivers" | "they" | "December"
+        P  ::= "in"
+        V  ::= "can" | "fish"
+    "#;
+
+    fn forest(grammar: &str, privileged: &[&str], input: &str) -> Forest {
+        let grammar = Grammar::from_bnf_str(grammar).unwrap();
+        let privileged = privileged
+            .iter()
+            .map(|name| Symbol::NonTerminal(name.to_string()))
+            .collect();
+        let mut parser = grammar.get_parser(privileged).unwrap();
+        parser
+            .parse_forest(input.split_whitespace().collect())
+            .unwrap()
+    }
+
+    fn sorted_trees(forest: &Forest) -> Vec<String> {
+        let mut trees: Vec<String> = forest.get_trees(None).map(|t| t.to_string()).collect();
+        trees.sort();
+        trees
+    }
+
+    #[test]
+    fn demo_sentence() {
+        let forest = forest(
+            DEMO,
+            &["N", "V", "P"],
+            "they can fish in rivers in December",
+        );
+
+        // The second to last tree is the one the parser gave before it kept every derivation
+        assert_eq!(forest.count_parses(), ParseCount::Finite(9));
+        assert_eq!(
+            sorted_trees(&forest),
+            vec![
+                "(S (NP (N they)) (VP (V can) (NP (N fish) (PP (P in) (NP (N rivers) (PP (P in) (NP (N December))))))))",
+                "(S (NP (N they)) (VP (V can) (VP (VP (V fish)) (PP (P in) (NP (N rivers) (PP (P in) (NP (N December))))))))",
+                "(S (NP (N they)) (VP (V can) (VP (VP (VP (V fish)) (PP (P in) (NP (N rivers)))) (PP (P in) (NP (N December))))))",
+                "(S (NP (N they)) (VP (VP (V can) (NP (N fish) (PP (P in) (NP (N rivers))))) (PP (P in) (NP (N December)))))",
+                "(S (NP (N they)) (VP (VP (V can) (NP (N fish))) (PP (P in) (NP (N rivers) (PP (P in) (NP (N December)))))))",
+                "(S (NP (N they)) (VP (VP (V can) (VP (V fish))) (PP (P in) (NP (N rivers) (PP (P in) (NP (N December)))))))",
+                "(S (NP (N they)) (VP (VP (V can) (VP (VP (V fish)) (PP (P in) (NP (N rivers))))) (PP (P in) (NP (N December)))))",
+                "(S (NP (N they)) (VP (VP (VP (V can) (NP (N fish))) (PP (P in) (NP (N rivers)))) (PP (P in) (NP (N December)))))",
+                "(S (NP (N they)) (VP (VP (VP (V can) (VP (V fish))) (PP (P in) (NP (N rivers)))) (PP (P in) (NP (N December)))))",
+            ]
+        );
+    }
+
+    #[test]
+    fn hidden_helpers_are_spliced_out() {
+        let forest = forest(r#"S ::= ("a" | "b")+ "c"?"#, &[], "a b a c");
+
+        assert_eq!(forest.count_parses(), ParseCount::Finite(1));
+        assert_eq!(sorted_trees(&forest), vec!["(S a b a c)"]);
+    }
+
+    #[test]
+    fn derivations_share_nodes() {
+        // 4862 binary bracketings of ten words, held in a forest of polynomial size
+        let forest = forest(r#"S ::= S S | "a""#, &[], &["a"; 10].join(" "));
+
+        assert_eq!(forest.count_parses(), ParseCount::Finite(4862));
+        assert!(forest.len() < 200, "{} nodes", forest.len());
+    }
+
+    #[test]
+    fn trees_are_each_parse_once() {
+        // Two roots, S ::= A and S ::= B, each over the 14 bracketings of five operands
+        let grammar = r#"
+            S ::= A | B
+            A ::= E
+            B ::= E
+            E ::= E "+" E | "n"
+        "#;
+        let forest = forest(grammar, &[], "n + n + n + n + n");
+
+        assert_eq!(forest.count_parses(), ParseCount::Finite(28));
+        let mut trees = forest.get_trees(None);
+        let distinct: HashSet<String> = trees.by_ref().map(|t| t.to_string()).collect();
+        assert_eq!(distinct.len(), 28);
+        assert!(trees.next().is_none());
+        assert!(trees.next().is_none());
+
+        assert_eq!(forest.get_trees(Some(5)).count(), 5);
+    }
+
+    #[test]
+    fn parse_counts_saturate() {
+        // The 59th Catalan number, about 1.5e32, is well past u64::MAX
+        let forest = forest(r#"S ::= S S | "a""#, &[], &["a"; 60].join(" "));
+
+        let count = forest.count_parses();
+        assert_eq!(count, ParseCount::Finite(u64::MAX));
+        assert_eq!(count.to_string(), format!("at least {}", u64::MAX));
+    }
+}
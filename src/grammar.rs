@@ -1,8 +1,14 @@
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 
+use crate::disambiguation::Disambiguation;
+use crate::error::{GrammarError, ParseError, ValidationReport};
 use crate::parser::Parser;
 
-#[derive(Hash, Clone, Debug)]
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+#[derive(Hash, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum Symbol {
     NonTerminal(String),
     Terminal(String),
@@ -15,22 +21,6 @@ pub enum SymbolType {
 
 pub type Rule = (Symbol, Vec<Symbol>);
 
-impl PartialEq for Symbol {
-    fn eq(&self, other: &Self) -> bool {
-        match self {
-            Symbol::NonTerminal(x) => match other {
-                Symbol::NonTerminal(y) => x.eq(y),
-                Symbol::Terminal(_) => false,
-            },
-            Symbol::Terminal(x) => match other {
-                Symbol::NonTerminal(_) => false,
-                Symbol::Terminal(y) => x.eq(y),
-            },
-        }
-    }
-}
-impl Eq for Symbol {}
-
 // Creates a set of non-terminals from a vector of strings
 pub fn create_non_terminal_set(non_terminals: Vec<&str>) -> HashSet<Symbol> {
     let mut set: HashSet<Symbol> = HashSet::new();
@@ -71,12 +61,27 @@ pub fn create_rule_set(rules: Vec<(&str, Vec<(&str, SymbolType)>)>) -> HashSet<R
     set
 }
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(try_from = "RawGrammar"))]
 pub struct Grammar {
+    #[cfg_attr(feature = "serde", serde(serialize_with = "sorted_set"))]
     non_terminals: HashSet<Symbol>,
+    #[cfg_attr(feature = "serde", serde(serialize_with = "sorted_set"))]
     terminals: HashSet<Symbol>,
     start: Symbol,
+    #[cfg_attr(feature = "serde", serde(serialize_with = "sorted_set"))]
     rules: HashSet<Rule>,
+    // Helper non-terminals that don't show up in parse trees, such as those generated from EBNF
+    #[cfg_attr(feature = "serde", serde(serialize_with = "sorted_set"))]
+    hidden: HashSet<Symbol>,
+    // Filters resolving ambiguity, see the disambiguation module
+    disambiguation: Disambiguation,
+    // Rule weights, see the pcfg module. Rules without one weigh 1.
+    #[cfg_attr(feature = "serde", serde(serialize_with = "weight_pairs::serialize"))]
+    weights: HashMap<Rule, f64>,
+    // Whether the weights are allowed not to sum to 1 for a non-terminal
+    unnormalised: bool,
 }
 
 impl Grammar {
@@ -85,44 +90,158 @@ impl Grammar {
         terminals: HashSet<Symbol>,
         start: Symbol,
         rules: HashSet<Rule>,
-    ) -> Result<Grammar, String> {
-        if !non_terminals.contains(&start) {
-            return Err("Starting symbol not in the set of non-terminals".to_string());
+    ) -> Result<Grammar, GrammarError> {
+        if let Some(error) = Grammar::validate(&non_terminals, &terminals, &start, &rules)
+            .into_iter()
+            .next()
+        {
+            return Err(error);
         }
 
-        for rule in &rules {
-            if !non_terminals.contains(&rule.0) {
-                return Err("Left side of rule is not a non-terminal".to_string());
-            }
-            for symbol in &rule.1 {
-                if !non_terminals.contains(&symbol) && !terminals.contains(&symbol) {
-                    return Err(
-                        "Symbol on right side of rule is neither a terminal, nor a non-terminal"
-                            .to_string(),
-                    );
-                }
-            }
+        Ok(Grammar::from_parts(non_terminals, terminals, start, rules))
+    }
+
+    // Like new, but reports every problem with the grammar instead of stopping at the first
+    pub fn new_validated(
+        non_terminals: HashSet<Symbol>,
+        terminals: HashSet<Symbol>,
+        start: Symbol,
+        rules: HashSet<Rule>,
+    ) -> Result<Grammar, ValidationReport> {
+        let errors = Grammar::validate(&non_terminals, &terminals, &start, &rules);
+        if !errors.is_empty() {
+            return Err(ValidationReport { errors });
         }
 
-        Ok(Grammar {
+        Ok(Grammar::from_parts(non_terminals, terminals, start, rules))
+    }
+
+    fn from_parts(
+        non_terminals: HashSet<Symbol>,
+        terminals: HashSet<Symbol>,
+        start: Symbol,
+        rules: HashSet<Rule>,
+    ) -> Grammar {
+        Grammar {
             non_terminals,
             terminals,
             start,
             rules,
-        })
+            hidden: HashSet::new(),
+            disambiguation: Disambiguation::default(),
+            weights: HashMap::new(),
+            unnormalised: false,
+        }
     }
 
-    pub fn get_parser(&self, privileged: HashSet<Symbol>) -> Result<Parser, String> {
+    // Checks the parts of a grammar, returning all the problems found. Rules are checked in
+    // sorted order so that the report is the same from run to run.
+    pub fn validate(
+        non_terminals: &HashSet<Symbol>,
+        terminals: &HashSet<Symbol>,
+        start: &Symbol,
+        rules: &HashSet<Rule>,
+    ) -> Vec<GrammarError> {
+        let mut errors = Vec::new();
+
+        if !non_terminals.contains(start) {
+            errors.push(GrammarError::StartNotNonTerminal(start.clone()));
+        }
+
+        for empty in [
+            Symbol::NonTerminal(String::new()),
+            Symbol::Terminal(String::new()),
+        ] {
+            if non_terminals.contains(&empty) || terminals.contains(&empty) {
+                errors.push(GrammarError::EmptyName(empty));
+            }
+        }
+
+        let mut sorted_rules: Vec<&Rule> = rules.iter().collect();
+        sorted_rules.sort();
+
+        for rule in sorted_rules {
+            if let Symbol::Terminal(_) = rule.0 {
+                errors.push(GrammarError::LhsIsTerminal(rule.clone()));
+            } else if !non_terminals.contains(&rule.0) {
+                errors.push(GrammarError::LhsNotNonTerminal(rule.clone()));
+            }
+
+            let mut reported = HashSet::new();
+            for symbol in &rule.1 {
+                if !non_terminals.contains(symbol)
+                    && !terminals.contains(symbol)
+                    && reported.insert(symbol)
+                {
+                    errors.push(GrammarError::UndeclaredSymbol {
+                        symbol: symbol.clone(),
+                        rule: rule.clone(),
+                    });
+                }
+            }
+        }
+
+        errors
+    }
+
+    pub fn get_parser(&self, privileged: HashSet<Symbol>) -> Result<Parser<'_>, ParseError> {
         Parser::new(self, privileged)
     }
 
-    pub fn get_starting_rule(&self) -> Result<Rule, String> {
-        for rule in &self.rules {
-            if rule.0 == self.start {
-                return Ok(rule.clone());
+    pub fn get_starting_rules(&self) -> Vec<Rule> {
+        self.get_rules(self.start.clone())
+    }
+
+    // Creates the rule S' -> S used to seed the parser with every production of the start
+    // symbol S, where S' is a fresh non-terminal
+    pub fn get_augmented_rule(&self) -> Rule {
+        let mut name = match &self.start {
+            Symbol::NonTerminal(name) | Symbol::Terminal(name) => name.clone(),
+        };
+        loop {
+            name.push('\'');
+            let augmented = Symbol::NonTerminal(name.clone());
+            if !self.non_terminals.contains(&augmented) {
+                return (augmented, vec![self.start.clone()]);
             }
         }
-        Err("No rule reducing the starting symbol".to_string())
+    }
+
+    pub fn get_start(&self) -> &Symbol {
+        &self.start
+    }
+
+    pub fn get_non_terminals(&self) -> &HashSet<Symbol> {
+        &self.non_terminals
+    }
+
+    pub fn get_terminals(&self) -> &HashSet<Symbol> {
+        &self.terminals
+    }
+
+    pub fn get_all_rules(&self) -> &HashSet<Rule> {
+        &self.rules
+    }
+
+    pub fn get_hidden(&self) -> &HashSet<Symbol> {
+        &self.hidden
+    }
+
+    pub fn get_disambiguation(&self) -> &Disambiguation {
+        &self.disambiguation
+    }
+
+    pub fn get_weight(&self, rule: &Rule) -> f64 {
+        self.weights.get(rule).copied().unwrap_or(1.0)
+    }
+
+    // The rules given a weight explicitly
+    pub fn get_weights(&self) -> &HashMap<Rule, f64> {
+        &self.weights
+    }
+
+    pub fn is_unnormalised(&self) -> bool {
+        self.unnormalised
     }
 
     pub fn get_rules(&self, symbol: Symbol) -> Vec<Rule> {
@@ -145,15 +264,173 @@ impl Grammar {
         self.non_terminals.contains(symbol)
     }
 
+    pub fn is_hidden(&self, symbol: &Symbol) -> bool {
+        self.hidden.contains(symbol)
+    }
+
+    pub(crate) fn set_hidden(&mut self, hidden: HashSet<Symbol>) {
+        self.hidden = hidden;
+    }
+
+    // Checks that a rule named by a filter or weight is in the grammar
+    pub(crate) fn check_rule(&self, rule: &Rule) -> Result<(), GrammarError> {
+        if self.rules.contains(rule) {
+            Ok(())
+        } else {
+            Err(GrammarError::UnknownRule(rule.clone()))
+        }
+    }
+
+    pub(crate) fn disambiguation_mut(&mut self) -> &mut Disambiguation {
+        &mut self.disambiguation
+    }
+
+    pub(crate) fn weights_mut(&mut self) -> &mut HashMap<Rule, f64> {
+        &mut self.weights
+    }
+
+    pub fn set_unnormalised(&mut self, unnormalised: bool) {
+        self.unnormalised = unnormalised;
+    }
+
     pub fn get_terminal_rule(&self, non_term: &Symbol, terminal: &Symbol) -> Option<Rule> {
         for rule in &self.rules {
-            if &rule.0 == non_term && rule.1.len() == 1 {
-                if &rule.1[0] == terminal {
-                    return Some(rule.clone());
-                }
+            if &rule.0 == non_term && rule.1.len() == 1 && &rule.1[0] == terminal {
+                return Some(rule.clone());
             }
         }
 
         None
     }
 }
+
+// With the serde feature, grammars and parse trees can be serialized. In JSON they look like:
+//
+//     Symbol    {"NonTerminal": "S"} or {"Terminal": "they"}
+//     Rule      [<Symbol>, [<Symbol>, ...]]
+//     Grammar   {"non_terminals": [<Symbol>, ...], "terminals": [<Symbol>, ...],
+//                "start": <Symbol>, "rules": [<Rule>, ...], "hidden": [<Symbol>, ...],
+//                "disambiguation": {"priorities": [[<Rule>, <Rule>], ...],
+//                                   "associativity": [["Left", [<Rule>, ...]], ...],
+//                                   "reject": [<Rule>, ...], "prefer": [...], "avoid": [...]},
+//                "weights": [[<Rule>, weight], ...], "unnormalised": false}
+//     ParseTree {"label": <Symbol>, "rule": <Rule> or null, "span": [start, end],
+//                "children": [<ParseTree>, ...]}
+//
+// Sets are written in sorted order so that the output is stable. When reading a grammar,
+// "hidden", "disambiguation", "weights" and "unnormalised" can be left out, and the grammar is
+// checked as Grammar::new and the disambiguation and weight setters would check it.
+
+#[cfg(feature = "serde")]
+fn sorted_set<T: Serialize + Ord, S: serde::Serializer>(
+    set: &HashSet<T>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    let mut sorted: Vec<&T> = set.iter().collect();
+    sorted.sort();
+    sorted.serialize(serializer)
+}
+
+// A grammar as it is read, before it is checked
+#[cfg(feature = "serde")]
+#[derive(Deserialize)]
+struct RawGrammar {
+    non_terminals: HashSet<Symbol>,
+    terminals: HashSet<Symbol>,
+    start: Symbol,
+    rules: HashSet<Rule>,
+    #[serde(default)]
+    hidden: HashSet<Symbol>,
+    #[serde(default)]
+    disambiguation: Disambiguation,
+    #[serde(default, deserialize_with = "weight_pairs::deserialize")]
+    weights: HashMap<Rule, f64>,
+    #[serde(default)]
+    unnormalised: bool,
+}
+
+#[cfg(feature = "serde")]
+impl TryFrom<RawGrammar> for Grammar {
+    type Error = GrammarError;
+
+    fn try_from(raw: RawGrammar) -> Result<Grammar, GrammarError> {
+        let mut grammar = Grammar::new(raw.non_terminals, raw.terminals, raw.start, raw.rules)?;
+        grammar.set_hidden(raw.hidden);
+
+        let disambiguation = raw.disambiguation;
+        for (higher, lower) in disambiguation.priorities {
+            grammar.add_priority(higher, lower)?;
+        }
+        for (associativity, rules) in disambiguation.associativity {
+            grammar.add_associativity(associativity, rules.into_iter().collect())?;
+        }
+        for rule in disambiguation.reject {
+            grammar.add_reject(rule)?;
+        }
+        for rule in disambiguation.prefer {
+            grammar.add_prefer(rule)?;
+        }
+        for rule in disambiguation.avoid {
+            grammar.add_avoid(rule)?;
+        }
+
+        grammar.set_unnormalised(raw.unnormalised);
+        grammar.set_weights(raw.weights)?;
+
+        Ok(grammar)
+    }
+}
+
+// Rules can't be the keys of a JSON object, so the weights are written as a list of rule and
+// weight pairs, sorted by rule so that the output is stable
+#[cfg(feature = "serde")]
+mod weight_pairs {
+    use std::collections::HashMap;
+
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    use super::Rule;
+
+    pub fn serialize<S: Serializer>(
+        weights: &HashMap<Rule, f64>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        let mut pairs: Vec<(&Rule, &f64)> = weights.iter().collect();
+        pairs.sort_by(|a, b| a.0.cmp(b.0));
+        pairs.serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<HashMap<Rule, f64>, D::Error> {
+        let pairs: Vec<(Rule, f64)> = Vec::deserialize(deserializer)?;
+        Ok(pairs.into_iter().collect())
+    }
+}
+
+#[cfg(all(test, feature = "serde"))]
+mod tests {
+    use super::Grammar;
+
+    #[test]
+    fn grammars_round_trip_through_json_in_sorted_order() {
+        let grammar =
+            Grammar::from_bnf_str("%left S ::= S S\nS ::= S S [0.25] | \"y\" [0.5] | \"x\" [0.25]")
+                .unwrap();
+
+        let json = serde_json::to_string(&grammar).unwrap();
+        assert!(json.contains(r#""terminals":[{"Terminal":"x"},{"Terminal":"y"}]"#));
+        assert_eq!(serde_json::from_str::<Grammar>(&json).unwrap(), grammar);
+    }
+
+    #[test]
+    fn invalid_grammars_are_rejected() {
+        let json = r#"{"non_terminals":[{"NonTerminal":"S"}],"terminals":[],
+            "start":{"NonTerminal":"S"},"rules":[[{"NonTerminal":"S"},[{"NonTerminal":"Q"}]]]}"#;
+
+        let error = serde_json::from_str::<Grammar>(json).unwrap_err();
+        assert!(error
+            .to_string()
+            .contains("neither a terminal, nor a non-terminal"));
+    }
+}
@@ -0,0 +1,253 @@
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashSet};
+
+use crate::forest::{Child, Forest, NodeId};
+use crate::tree::ParseTree;
+
+// The k best parses of a weighted grammar, best first, found lazily over the parse forest as in
+// Huang and Chiang's "Better k-best parsing" (2005). A derivation of a node is a packed node
+// along with a rank for each of its children, and the next best derivations of a node are always
+// among the neighbours of the ones found so far: those with one child's rank one higher.
+
+// A derivation of a node. The ranks are 0 for a missing child or a terminal.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct Derivation {
+    // The natural log of the derivation's weight
+    pub score: f64,
+    // Index of the packed node among the node's alternatives
+    pub packed: usize,
+    pub left: usize,
+    pub right: usize,
+}
+
+// Ordered by score and then by ranks, lower ranks first, so that ties come out in a fixed order
+struct Candidate(Derivation);
+
+impl PartialEq for Candidate {
+    fn eq(&self, other: &Candidate) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Candidate {}
+
+impl PartialOrd for Candidate {
+    fn partial_cmp(&self, other: &Candidate) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Candidate {
+    fn cmp(&self, other: &Candidate) -> Ordering {
+        let key = |d: &Derivation| (d.packed, d.left, d.right);
+        self.0
+            .score
+            .total_cmp(&other.0.score)
+            .then_with(|| key(&other.0).cmp(&key(&self.0)))
+    }
+}
+
+#[derive(Default)]
+struct NodeRanking {
+    // The best derivations of the node found so far, best first
+    found: Vec<Derivation>,
+    // How many of the found derivations have had their neighbours queued
+    expanded: usize,
+    candidates: BinaryHeap<Candidate>,
+    // Every (packed, left, right) that has been queued, so that none is queued twice
+    queued: HashSet<(usize, usize, usize)>,
+    started: bool,
+    // Set while the node's derivations are being worked out. A node met again then is on a
+    // cycle, and its derivations are left out.
+    busy: bool,
+}
+
+// The derivations of the nodes of a forest found so far, indexed by NodeId
+pub(crate) struct Ranking {
+    nodes: Vec<NodeRanking>,
+}
+
+impl Ranking {
+    fn new(forest: &Forest) -> Ranking {
+        Ranking {
+            nodes: (0..forest.len()).map(|_| NodeRanking::default()).collect(),
+        }
+    }
+
+    // The derivation of the node with the given rank, which has to have been found
+    pub(crate) fn get_derivation(&self, id: NodeId, rank: usize) -> &Derivation {
+        &self.nodes[id.0].found[rank]
+    }
+
+    // Finds the derivation of the node with the given rank if it has one, returning its score
+    fn find(&mut self, forest: &Forest, id: NodeId, rank: usize) -> Option<f64> {
+        if let Some(derivation) = self.nodes[id.0].found.get(rank) {
+            return Some(derivation.score);
+        }
+        if self.nodes[id.0].busy {
+            return None;
+        }
+        self.nodes[id.0].busy = true;
+
+        if !self.nodes[id.0].started {
+            self.nodes[id.0].started = true;
+            for packed in 0..forest.get_alternatives(id).len() {
+                self.queue(forest, id, (packed, 0, 0));
+            }
+        }
+
+        while self.nodes[id.0].found.len() <= rank {
+            // The neighbours of a derivation are only needed once it has been handed out
+            while self.nodes[id.0].expanded < self.nodes[id.0].found.len() {
+                let last = self.nodes[id.0].found[self.nodes[id.0].expanded];
+                self.nodes[id.0].expanded += 1;
+                self.queue(forest, id, (last.packed, last.left + 1, last.right));
+                self.queue(forest, id, (last.packed, last.left, last.right + 1));
+            }
+
+            match self.nodes[id.0].candidates.pop() {
+                Some(Candidate(derivation)) => self.nodes[id.0].found.push(derivation),
+                None => break,
+            }
+        }
+
+        self.nodes[id.0].busy = false;
+        self.nodes[id.0]
+            .found
+            .get(rank)
+            .map(|derivation| derivation.score)
+    }
+
+    // Queues a derivation of the node as a candidate, if its children have the given ranks
+    fn queue(&mut self, forest: &Forest, id: NodeId, key: (usize, usize, usize)) {
+        if !self.nodes[id.0].queued.insert(key) {
+            return;
+        }
+
+        let (packed, left, right) = key;
+        let alternative = forest.get_alternatives(id)[packed];
+        let left_score = match alternative.left {
+            Some(child) => self.find(forest, child, left),
+            None => (left == 0).then_some(0.0),
+        };
+        let right_score = match alternative.right {
+            Some(Child::Node(child)) => self.find(forest, child, right),
+            _ => (right == 0).then_some(0.0),
+        };
+        let (Some(left_score), Some(right_score)) = (left_score, right_score) else {
+            return;
+        };
+
+        // The rule's weight counts once, at its complete node
+        let mut score = left_score + right_score;
+        if forest.is_complete(id) {
+            score += forest.get_grammar().log_weight(forest.get_node(id).rule);
+        }
+        self.nodes[id.0].candidates.push(Candidate(Derivation {
+            score,
+            packed,
+            left,
+            right,
+        }));
+    }
+}
+
+// An iterator over the trees of a forest with the natural logs of their weights, best first
+pub struct BestTrees<'f> {
+    forest: &'f Forest,
+    ranking: Ranking,
+    // The rank of the next derivation of each root
+    next: Vec<usize>,
+    remaining: Option<usize>,
+}
+
+impl Iterator for BestTrees<'_> {
+    type Item = (ParseTree, f64);
+
+    fn next(&mut self) -> Option<(ParseTree, f64)> {
+        while self.remaining != Some(0) {
+            // The best of the next derivations of the roots
+            let mut best: Option<(usize, f64)> = None;
+            for (index, &root) in self.forest.get_roots().iter().enumerate() {
+                if let Some(score) = self.ranking.find(self.forest, root, self.next[index]) {
+                    if best.is_none_or(|(_, best_score)| score > best_score) {
+                        best = Some((index, score));
+                    }
+                }
+            }
+            let (index, score) = best?;
+
+            let root = self.forest.get_roots()[index];
+            let rank = self.next[index];
+            self.next[index] += 1;
+
+            // A node's derivations can still go round a cycle if they were found while working
+            // out a node outside it, so the tree is checked
+            if let Some(tree) = self.forest.build_ranked_tree(root, rank, &self.ranking) {
+                if let Some(remaining) = &mut self.remaining {
+                    *remaining -= 1;
+                }
+                return Some((tree, score));
+            }
+        }
+
+        None
+    }
+}
+
+impl Forest {
+    // Returns an iterator over the trees of every parse in order of weight, best first, stopping
+    // after `limit` trees if given. Ties come out in a fixed order. Derivations going round a
+    // cycle of the grammar are left out.
+    pub fn get_best_trees(&self, limit: Option<usize>) -> BestTrees<'_> {
+        BestTrees {
+            forest: self,
+            ranking: Ranking::new(self),
+            next: vec![0; self.get_roots().len()],
+            remaining: limit,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use crate::forest::ParseCount;
+    use crate::grammar::Grammar;
+
+    #[test]
+    fn best_trees_come_out_best_first() {
+        let grammar = Grammar::from_bnf_str(
+            r#"
+            S  ::= NP VP [1]
+            NP ::= N [0.6] | NP PP [0.4]
+            PP ::= P NP [1]
+            VP ::= V NP [0.5] | VP PP [0.3] | V [0.2]
+            N  ::= "they" [0.5] | "fish" [0.3] | "rivers" [0.2]
+            V  ::= "fish" [1]
+            P  ::= "in" [1]
+            "#,
+        )
+        .unwrap();
+        let mut parser = grammar.get_parser(HashSet::new()).unwrap();
+        let forest = parser
+            .parse_forest("they fish fish in rivers in rivers".split(' ').collect())
+            .unwrap();
+        let ParseCount::Finite(count) = forest.count_parses() else {
+            panic!("the grammar has no cycles");
+        };
+        assert!(count > 1);
+
+        // Asking for more trees than there are gives every parse once
+        let best: Vec<_> = forest.get_best_trees(Some(count as usize + 5)).collect();
+        assert_eq!(best.len(), count as usize);
+        let distinct: HashSet<String> = best.iter().map(|(tree, _)| tree.to_string()).collect();
+        assert_eq!(distinct.len(), best.len());
+
+        for pair in best.windows(2) {
+            assert!(pair[0].1 >= pair[1].1, "{} before {}", pair[0].1, pair[1].1);
+        }
+        assert_eq!(Some(best[0].clone()), forest.get_best_tree());
+    }
+}
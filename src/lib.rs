@@ -0,0 +1,17 @@
+pub mod ambiguity;
+pub mod analysis;
+pub mod bnf;
+pub mod disambiguation;
+pub mod ebnf;
+pub mod error;
+pub mod evaluation;
+pub mod forest;
+pub mod grammar;
+pub mod kbest;
+pub mod lint;
+pub mod parser;
+pub mod pcfg;
+pub mod symbols;
+pub mod training;
+pub mod tree;
+pub mod treebank;
@@ -0,0 +1,300 @@
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+
+use crate::bnf::rule_to_bnf;
+use crate::grammar::{Grammar, Rule, Symbol};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Severity {
+    // The grammar has rules that can't be used, or parses it can't count
+    Error,
+    // The grammar has something it doesn't need, or parses that look the same
+    Warning,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum LintKind {
+    // The non-terminal derives no string of terminals, so its rules are never used
+    Unproductive(Symbol),
+    // The non-terminals derive each other through rules whose other symbols can all be empty, so
+    // an input they derive has infinitely many parses
+    UnitCycle(Vec<Symbol>),
+    // No sentence derived from the start symbol uses the non-terminal
+    Unreachable(Symbol),
+    // The terminal is on the right side of no rule
+    UnusedTerminal(Symbol),
+    // A rule written more than once, or rules that only differ in hidden helpers deriving the
+    // same, as when the same EBNF is written twice for a non-terminal. The latter make every
+    // parse using them ambiguous.
+    DuplicateRule,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Lint {
+    pub severity: Severity,
+    pub kind: LintKind,
+    // The rules the problem is about, if there are any
+    pub rules: Vec<Rule>,
+}
+
+// Every problem found by linting a grammar, errors first
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LintReport {
+    pub lints: Vec<Lint>,
+}
+
+impl LintReport {
+    pub fn is_empty(&self) -> bool {
+        self.lints.is_empty()
+    }
+
+    pub fn has_errors(&self) -> bool {
+        self.lints
+            .iter()
+            .any(|lint| lint.severity == Severity::Error)
+    }
+
+    fn add(&mut self, kind: LintKind, rules: Vec<Rule>) {
+        let severity = match kind {
+            LintKind::Unproductive(_) | LintKind::UnitCycle(_) => Severity::Error,
+            _ => Severity::Warning,
+        };
+        self.lints.push(Lint {
+            severity,
+            kind,
+            rules,
+        });
+    }
+
+    // Adds the rules that were written more than once, see Grammar::lint_bnf_str
+    pub(crate) fn add_duplicates(&mut self, duplicates: Vec<Rule>) {
+        for rule in duplicates {
+            self.add(LintKind::DuplicateRule, vec![rule]);
+        }
+        self.sort();
+    }
+
+    fn sort(&mut self) {
+        self.lints
+            .sort_by(|a, b| (a.severity, &a.kind, &a.rules).cmp(&(b.severity, &b.kind, &b.rules)));
+        self.lints.dedup();
+    }
+}
+
+impl Grammar {
+    // Looks for rules and symbols that can't be used or that make parses ambiguous. Rules
+    // written more than once can only be found in the text of a grammar, see lint_bnf_str.
+    pub fn lint(&self) -> LintReport {
+        let mut report = LintReport { lints: Vec::new() };
+        let mut non_terminals: Vec<&Symbol> = self.get_non_terminals().iter().collect();
+        non_terminals.sort();
+        let rules_of = |symbol: &Symbol| {
+            let mut rules = self.get_rules(symbol.clone());
+            rules.sort();
+            rules
+        };
+
+        // Productive non-terminals are found by adding those with a rule whose right side is
+        // all terminals and productive non-terminals until there are no more
+        let mut productive: HashSet<&Symbol> = HashSet::new();
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for (lhs, right_side) in self.get_all_rules() {
+                if !productive.contains(lhs)
+                    && right_side
+                        .iter()
+                        .all(|symbol| self.in_terminals(symbol) || productive.contains(symbol))
+                {
+                    productive.insert(lhs);
+                    changed = true;
+                }
+            }
+        }
+        for &symbol in &non_terminals {
+            if !productive.contains(symbol) {
+                report.add(LintKind::Unproductive(symbol.clone()), rules_of(symbol));
+            }
+        }
+
+        let analysis = self.get_analysis();
+        for cycle in self.unit_cycles(|right_side| analysis.is_sequence_nullable(right_side)) {
+            let mut rules: Vec<Rule> = self
+                .get_all_rules()
+                .iter()
+                .filter(|rule| cycle.contains(&rule.0))
+                .filter(|rule| {
+                    rule.1.iter().enumerate().any(|(position, symbol)| {
+                        cycle.contains(symbol)
+                            && analysis.is_sequence_nullable(&rule.1[..position])
+                            && analysis.is_sequence_nullable(&rule.1[position + 1..])
+                    })
+                })
+                .cloned()
+                .collect();
+            rules.sort();
+            report.add(LintKind::UnitCycle(cycle), rules);
+        }
+
+        let mut reachable = HashSet::from([self.get_start()]);
+        let mut stack = vec![self.get_start()];
+        while let Some(symbol) = stack.pop() {
+            for (lhs, right_side) in self.get_all_rules() {
+                if lhs == symbol {
+                    for symbol in right_side {
+                        if reachable.insert(symbol) {
+                            stack.push(symbol);
+                        }
+                    }
+                }
+            }
+        }
+        for &symbol in &non_terminals {
+            if !reachable.contains(symbol) {
+                report.add(LintKind::Unreachable(symbol.clone()), rules_of(symbol));
+            }
+        }
+
+        let used: HashSet<&Symbol> = self
+            .get_all_rules()
+            .iter()
+            .flat_map(|rule| &rule.1)
+            .collect();
+        let mut terminals: Vec<&Symbol> = self.get_terminals().iter().collect();
+        terminals.sort();
+        for symbol in terminals {
+            if !used.contains(symbol) {
+                report.add(LintKind::UnusedTerminal(symbol.clone()), Vec::new());
+            }
+        }
+
+        // Rules differing only in hidden helpers that derive the same things
+        let mut forms: BTreeMap<(&Symbol, String), Vec<Rule>> = BTreeMap::new();
+        for rule in self.get_all_rules() {
+            let form = self.symbols_form(&rule.1, &mut vec![&rule.0]);
+            forms.entry((&rule.0, form)).or_default().push(rule.clone());
+        }
+        for (_, mut rules) in forms {
+            if rules.len() > 1 {
+                rules.sort();
+                report.add(LintKind::DuplicateRule, rules);
+            }
+        }
+
+        report.sort();
+        report
+    }
+
+    // The groups of non-terminals that derive each other through rules whose other symbols pass
+    // `is_empty`, each sorted
+    fn unit_cycles(&self, is_empty: impl Fn(&[Symbol]) -> bool) -> Vec<Vec<Symbol>> {
+        let mut units: BTreeMap<&Symbol, HashSet<&Symbol>> = BTreeMap::new();
+        for (lhs, right_side) in self.get_all_rules() {
+            for (position, symbol) in right_side.iter().enumerate() {
+                if self.in_non_terminals(symbol)
+                    && is_empty(&right_side[..position])
+                    && is_empty(&right_side[position + 1..])
+                {
+                    units.entry(lhs).or_default().insert(symbol);
+                }
+            }
+        }
+
+        // The non-terminals each one derives through one or more unit steps
+        let mut derived: BTreeMap<&Symbol, HashSet<&Symbol>> = BTreeMap::new();
+        for &start in units.keys() {
+            let mut seen = HashSet::new();
+            let mut stack = vec![start];
+            while let Some(symbol) = stack.pop() {
+                for &next in units.get(symbol).into_iter().flatten() {
+                    if seen.insert(next) {
+                        stack.push(next);
+                    }
+                }
+            }
+            derived.insert(start, seen);
+        }
+
+        let mut cycles: Vec<Vec<Symbol>> = Vec::new();
+        for (&symbol, reached) in &derived {
+            if !reached.contains(symbol) || cycles.iter().any(|cycle| cycle.contains(symbol)) {
+                continue;
+            }
+            let mut cycle: Vec<Symbol> = reached
+                .iter()
+                .filter(|&&other| derived.get(other).is_some_and(|back| back.contains(symbol)))
+                .map(|&other| other.clone())
+                .collect();
+            cycle.sort();
+            cycles.push(cycle);
+        }
+        cycles
+    }
+
+    // Describes a right side with each hidden helper written out as what it derives, so that
+    // helpers made from the same EBNF come out the same. `path` holds the symbols being written
+    // out, which a recursive helper refers to by their depth.
+    fn symbols_form<'g>(&'g self, right_side: &'g [Symbol], path: &mut Vec<&'g Symbol>) -> String {
+        let mut items = Vec::new();
+        for symbol in right_side {
+            if let Some(depth) = path.iter().position(|&other| other == symbol) {
+                items.push(format!("#{}", depth));
+            } else if self.is_hidden(symbol) {
+                path.push(symbol);
+                let mut alternatives: Vec<String> = self
+                    .get_all_rules()
+                    .iter()
+                    .filter(|rule| &rule.0 == symbol)
+                    .map(|rule| self.symbols_form(&rule.1, path))
+                    .collect();
+                alternatives.sort();
+                path.pop();
+                items.push(format!("({})", alternatives.join(" | ")));
+            } else {
+                items.push(symbol.to_string());
+            }
+        }
+        items.join(" ")
+    }
+}
+
+impl fmt::Display for LintReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} problem(s) found by linting", self.lints.len())?;
+        for lint in &self.lints {
+            write!(f, "\n  {}", lint)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Lint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let severity = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+        write!(f, "{}: ", severity)?;
+        match &self.kind {
+            LintKind::Unproductive(symbol) => {
+                write!(f, "{} derives no string of terminals", symbol)?
+            }
+            LintKind::UnitCycle(cycle) => match &cycle[..] {
+                [symbol] => write!(f, "{} derives itself in a cycle", symbol)?,
+                _ => {
+                    let cycle: Vec<String> = cycle.iter().map(Symbol::to_string).collect();
+                    write!(f, "{} derive each other in a cycle", cycle.join(", "))?
+                }
+            },
+            LintKind::Unreachable(symbol) => {
+                write!(f, "{} can't be reached from the start symbol", symbol)?
+            }
+            LintKind::UnusedTerminal(symbol) => write!(f, "terminal {} is never used", symbol)?,
+            LintKind::DuplicateRule => write!(f, "duplicate rule")?,
+        }
+        for rule in &self.rules {
+            write!(f, "\n    {}", rule_to_bnf(rule))?;
+        }
+        Ok(())
+    }
+}
@@ -1,10 +1,47 @@
-pub mod grammar;
-pub mod parser;
+use std::env;
+use std::error::Error;
 
-use grammar::SymbolType::{NT, T};
-use grammar::{create_non_terminal_set, create_rule_set, create_terminal_set, Grammar};
+use earley_parser::grammar::SymbolType::{NT, T};
+use earley_parser::grammar::{
+    create_non_terminal_set, create_rule_set, create_terminal_set, Grammar, Symbol,
+};
+use earley_parser::treebank::read_treebank_file;
 
-fn main() -> Result<(), String> {
+const USAGE: &str = "usage: earley-parser [evaluate <grammar.bnf> <gold treebank>]";
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let args: Vec<String> = env::args().skip(1).collect();
+    match &args[..] {
+        [] => demo(),
+        [command, grammar, gold] if command == "evaluate" => evaluate(grammar, gold),
+        _ => Err(USAGE.into()),
+    }
+}
+
+// Parses the words of each gold tree with the grammar and prints PARSEVAL scores. The
+// pre-terminals of the gold trees are parsed as privileged. If the grammar starts with the TOP
+// symbol that a grammar read off the gold trees would add, that symbol isn't scored.
+fn evaluate(grammar: &str, gold: &str) -> Result<(), Box<dyn Error>> {
+    let grammar = Grammar::from_bnf_file(grammar)?;
+    let gold = read_treebank_file(gold)?;
+
+    let treebank = Grammar::from_treebank(&gold)?;
+    let privileged = treebank
+        .privileged
+        .into_iter()
+        .filter(|symbol| grammar.in_non_terminals(symbol))
+        .collect();
+    let added_root = treebank
+        .added_root
+        .filter(|root| root == grammar.get_start());
+    let mut parser = grammar.get_parser(privileged)?;
+    println!("{}", parser.evaluate(&gold, added_root.as_ref()));
+
+    Ok(())
+}
+
+// Parses an ambiguous sentence with a small grammar of English
+fn demo() -> Result<(), Box<dyn Error>> {
     let non_terminals = create_non_terminal_set(vec!["S", "NP", "VP", "PP", "N", "V", "P"]);
     let terminals = create_terminal_set(vec!["can", "fish", "rivers", "they", "in", "December"]);
 
@@ -30,7 +67,7 @@ fn main() -> Result<(), String> {
     let grammar = Grammar::new(
         non_terminals,
         terminals,
-        grammar::Symbol::NonTerminal("S".to_string()),
+        Symbol::NonTerminal("S".to_string()),
         rules,
     )?;
 
@@ -38,9 +75,15 @@ fn main() -> Result<(), String> {
 
     let mut parser = grammar.get_parser(privileged)?;
 
-    parser.parse(vec![
+    let forest = parser.parse_forest(vec![
         "they", "can", "fish", "in", "rivers", "in", "December",
     ])?;
 
+    println!("{} parse(s)\n", forest.count_parses());
+    println!("{}\n", forest.get_ambiguity_report());
+    for tree in forest.get_trees(None) {
+        println!("{:#}\n", tree);
+    }
+
     Ok(())
 }
@@ -1,288 +1,911 @@
-use crate::grammar::Symbol::{NonTerminal, Terminal};
-use crate::grammar::{Grammar, Rule, Symbol};
-
-use std::collections::HashSet;
-
-#[derive(Debug, Clone)]
+use crate::disambiguation::RuleFilters;
+use crate::error::ParseError;
+use crate::forest::{Child, Forest, Node, NodeId, Packed};
+use crate::grammar::Symbol::Terminal;
+use crate::grammar::{Grammar, Symbol};
+use crate::symbols::{CompiledGrammar, RuleId, SymbolId};
+use crate::tree::ParseTree;
+
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 struct DottedRule {
-    rule: Rule,
+    rule: RuleId,
     dot_pos: usize,
 }
 
-impl PartialEq for DottedRule {
-    fn eq(&self, other: &Self) -> bool {
-        self.rule == other.rule && self.dot_pos == other.dot_pos
+impl DottedRule {
+    fn advance(self) -> DottedRule {
+        DottedRule {
+            rule: self.rule,
+            dot_pos: self.dot_pos + 1,
+        }
     }
 }
-impl Eq for DottedRule {}
+
+// Refers to an edge by the Earley set it is in and its index within that set
+type EdgeRef = (usize, usize);
+
+// How the dot of an edge got to where it is. Following the links back to the start of the rule
+// gives the edge's history.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Link {
+    // The dot was moved from the `pred` edge over a terminal, or over a non-terminal whose
+    // completed edge is `child`
+    Step {
+        pred: EdgeRef,
+        child: Option<EdgeRef>,
+    },
+    // The completed edge of a privileged non-terminal, scanned directly
+    Scanned,
+    // A completed edge added by Leo's optimisation when `child` was completed, skipping the chain
+    // of edges in between. The chain is filled back in before trees are built.
+    Leo {
+        child: EdgeRef,
+    },
+}
 
 #[derive(Debug, Clone)]
 struct Edge {
     d_rule: DottedRule,
     span: (usize, usize),
-    history: Vec<usize>,
+    // One link for each way of getting the edge, empty while the dot is at the start of the rule
+    links: Vec<Link>,
 }
 
-impl PartialEq for Edge {
-    fn eq(&self, other: &Self) -> bool {
-        self.d_rule == other.d_rule && self.span == other.span
-    }
+// The edges ending at one position of the input
+#[derive(Debug, Default)]
+struct EarleySet {
+    edges: Vec<Edge>,
+    // Edges by dotted rule and start position, so that duplicates are found in constant time.
+    // The history of a duplicate is kept as another link of the edge.
+    index: HashMap<(DottedRule, usize), usize>,
+    // Edges with the dot in front of each non-terminal, for completion
+    waiting: HashMap<SymbolId, Vec<usize>>,
+    // Leo's transitive items: for a non-terminal, the topmost completed item (dotted rule and
+    // start position) that completing it here leads to, if the set is deterministic for it
+    leo: HashMap<SymbolId, Option<(DottedRule, usize)>>,
 }
-impl Eq for Edge {}
 
-// Vec index serves as Edge id
-type Chart = Vec<Edge>;
+// Earley set i holds the edges whose span ends at position i of the input
+type Chart = Vec<EarleySet>;
 
 pub struct Parser<'g> {
     chart: Chart,
     grammar: &'g Grammar,
-    privileged: HashSet<Symbol>,
-    starting_rule: Rule,
+    compiled: Rc<CompiledGrammar>,
+    // Indexed by SymbolId
+    privileged: Vec<bool>,
+    // Rules of privileged non-terminals by their left side and terminal, for scanning
+    terminal_rules: HashMap<(SymbolId, SymbolId), RuleId>,
+    // The terminals each rule's right side can start with, by RuleId, or None if it can derive
+    // the empty string. A rule is only predicted if the next input symbol is among them.
+    lookahead: Vec<Option<HashSet<SymbolId>>>,
+    // The FIRST set of every non-terminal, by SymbolId, for reporting what a failed parse expected
+    first: Vec<Vec<SymbolId>>,
+    filters: RuleFilters,
 }
 
 impl<'g> Parser<'g> {
-    pub fn new(grammar: &Grammar, privileged: HashSet<Symbol>) -> Result<Parser, String> {
+    pub fn new(grammar: &Grammar, privileged: HashSet<Symbol>) -> Result<Parser<'_>, ParseError> {
         // Check that the set of privileged instructions is a subset of the grammar's non-terminals
         for non_term in &privileged {
-            if !grammar.in_non_terminals(&non_term) {
-                return Err("Privileged non-terminal, not in the set of non-terminals".to_string());
+            if !grammar.in_non_terminals(non_term) {
+                return Err(ParseError::PrivilegedNotNonTerminal(non_term.clone()));
             }
         }
 
-        let starting_rule = grammar.get_starting_rule()?;
+        if grammar.get_starting_rules().is_empty() {
+            return Err(ParseError::NoStartingRule(grammar.get_start().clone()));
+        }
 
-        let mut chart = Chart::new();
+        let analysis = grammar.get_analysis();
+        let compiled = CompiledGrammar::new(grammar, &analysis);
 
-        // Initialize the chart
-        chart.push(Edge {
-            d_rule: DottedRule {
-                rule: starting_rule.clone(),
-                dot_pos: 0,
-            },
-            span: (0, 0),
-            history: Vec::new(),
-        });
+        let mut privileged_ids = vec![false; compiled.symbols.len()];
+        for non_term in &privileged {
+            privileged_ids[compiled.symbols.id(non_term).unwrap().index()] = true;
+        }
+
+        let mut terminal_rules = HashMap::new();
+        for (id, rule) in compiled.rules.iter().enumerate() {
+            if let [terminal] = rule.rhs[..] {
+                if privileged_ids[rule.lhs.index()] && compiled.is_terminal(terminal) {
+                    terminal_rules.insert((rule.lhs, terminal), RuleId(id as u32));
+                }
+            }
+        }
+
+        let to_ids = |symbols: &HashSet<Symbol>| -> HashSet<SymbolId> {
+            symbols
+                .iter()
+                .map(|symbol| compiled.symbols.id(symbol).unwrap())
+                .collect()
+        };
+        let lookahead = (0..compiled.rules.len())
+            .map(|id| {
+                let (_, right_side) = compiled.to_rule(RuleId(id as u32));
+                if analysis.is_sequence_nullable(&right_side) {
+                    None
+                } else {
+                    Some(to_ids(&analysis.get_sequence_first(&right_side)))
+                }
+            })
+            .collect();
+        let first = (0..compiled.symbols.len())
+            .map(|id| {
+                let symbol = compiled.symbols.symbol(SymbolId(id as u32));
+                to_ids(analysis.get_first(symbol)).into_iter().collect()
+            })
+            .collect();
+
+        let filters = RuleFilters::new(grammar.get_disambiguation(), &compiled);
 
         Ok(Parser {
-            chart,
+            chart: Chart::new(),
             grammar,
-            privileged,
-            starting_rule,
+            compiled: Rc::new(compiled),
+            privileged: privileged_ids,
+            terminal_rules,
+            lookahead,
+            first,
+            filters,
         })
     }
 
-    pub fn parse(&mut self, input: Vec<&str>) -> Result<(), String> {
+    // Parses the input, returning a tree for each parse of the start symbol. Ambiguous input can
+    // have exponentially many, see parse_forest and Forest::get_trees for taking them one at a
+    // time.
+    pub fn parse(&mut self, input: Vec<&str>) -> Result<Vec<ParseTree>, ParseError> {
+        Ok(self.parse_forest(input)?.get_trees(None).collect())
+    }
+
+    // Parses the input, returning its most probable tree and the natural log of its weight, see
+    // the pcfg module
+    pub fn parse_best(&mut self, input: Vec<&str>) -> Result<(ParseTree, f64), ParseError> {
+        let forest = self.parse_forest(input)?;
+        Ok(forest
+            .get_best_tree()
+            .expect("a forest has at least one root"))
+    }
+
+    // Parses the input, returning its k most probable trees with their log-probabilities, best
+    // first
+    pub fn parse_k_best(
+        &mut self,
+        input: Vec<&str>,
+        k: usize,
+    ) -> Result<Vec<(ParseTree, f64)>, ParseError> {
+        let forest = self.parse_forest(input)?;
+        Ok(forest.get_best_trees(Some(k)).collect())
+    }
+
+    // Parses the input, returning a forest of every parse of the start symbol
+    pub fn parse_forest(&mut self, input: Vec<&str>) -> Result<Forest, ParseError> {
         let mut input_terminals = Vec::new();
         // First check that the input is comprised of terminals in the grammar and convert them
-        for string in &input {
+        for (index, string) in input.iter().enumerate() {
             let terminal = Terminal(string.to_string());
             if !self.grammar.in_terminals(&terminal) {
-                return Err("Input symbol not in grammar's terminals".to_string());
+                return Err(ParseError::UnknownTerminal { index, terminal });
+            }
+            input_terminals.push(self.compiled.symbols.id(&terminal).unwrap());
+        }
+
+        // Initialize the chart
+        self.chart = (0..=input_terminals.len())
+            .map(|_| EarleySet::default())
+            .collect();
+        let augmented = DottedRule {
+            rule: self.compiled.augmented_rule,
+            dot_pos: 0,
+        };
+        self.add_edge(0, augmented, (0, 0), None);
+
+        // Process the sets in order. Each set is a worklist, the edges added to it while it is
+        // being processed are processed in turn.
+        for i in 0..self.chart.len() {
+            let mut j = 0;
+            while j < self.chart[i].edges.len() {
+                self.process(&input_terminals, i, j);
+                j += 1;
             }
-            input_terminals.push(terminal);
+
+            // Nothing could be scanned, so no later set can have any edges
+            if i < input_terminals.len() && self.chart[i + 1].edges.is_empty() {
+                break;
+            }
+        }
+
+        // Fill in the edges skipped by Leo's optimisation, starting from S' -> S as the start
+        // symbol's edges can be among them
+        let end = input_terminals.len();
+        if let Some(&j) = self.chart[end].index.get(&(augmented.advance(), 0)) {
+            self.expand_leo_links(vec![(end, j)]);
         }
 
-        while self.get_end_edges(&input_terminals).is_empty() {
-            self.predict();
-            self.scan(&input_terminals);
-            self.cont();
+        let end_edges = self.get_end_edges(end);
+        if end_edges.is_empty() {
+            return Err(self.failure(&input_terminals));
+        }
+        self.expand_leo_links(end_edges.clone());
+
+        // The filters compare the completed edges of a non-terminal over a span, which may be
+        // out of reach of the end edges, so every Leo link has to go
+        if !self.filters.is_empty() {
+            let leo_edges = (0..self.chart.len())
+                .flat_map(|i| (0..self.chart[i].edges.len()).map(move |j| (i, j)))
+                .filter(|&edge_ref| {
+                    let links = &self.edge(edge_ref).links;
+                    links.iter().any(|link| matches!(link, Link::Leo { .. }))
+                })
+                .collect();
+            self.expand_leo_links(leo_edges);
         }
 
-        for edge in self.get_end_edges(&input_terminals) {
-            self.print_parse_tree(edge);
-            println!("\n")
+        let mut validity = HashMap::new();
+        let roots: Vec<EdgeRef> = end_edges
+            .into_iter()
+            .filter(|&edge_ref| self.is_valid(edge_ref, &mut validity))
+            .collect();
+        if roots.is_empty() {
+            return Err(ParseError::Filtered);
         }
 
-        Ok(())
+        Ok(self.build_forest(input_terminals, &roots, &mut validity))
     }
 
-    fn get_end_edges(&mut self, input: &Vec<Symbol>) -> Vec<Edge> {
-        let end = Edge {
-            d_rule: DottedRule {
-                rule: self.starting_rule.clone(),
-                dot_pos: self.starting_rule.1.len(),
-            },
-            span: (0, input.len()),
-            history: Vec::new(),
+    // The symbol after the dot, if the edge's rule isn't complete
+    fn next_symbol(&self, d_rule: DottedRule) -> Option<SymbolId> {
+        let rule = self.compiled.rule(d_rule.rule);
+        rule.rhs.get(d_rule.dot_pos).copied()
+    }
+
+    fn process(&mut self, input: &[SymbolId], i: usize, j: usize) {
+        let Edge { d_rule, span, .. } = self.chart[i].edges[j];
+
+        let Some(next) = self.next_symbol(d_rule) else {
+            self.cont(i, j, d_rule, span);
+            return;
         };
 
-        let mut end_edges = Vec::new();
+        if self.compiled.is_terminal(next) {
+            self.scan(input, i, j, d_rule, span);
+            return;
+        }
+
+        // If the non-terminal after the dot can derive the empty string, the dot can also be
+        // moved past it straight away (Aycock and Horspool's nullable-aware prediction)
+        if self.compiled.nullable_rule(next).is_some() {
+            // The skipped non-terminal still needs a child in the tree, so link to an empty
+            // derivation of it, and to any others already completed here. Those completed later
+            // find this edge waiting on them.
+            let mut children = vec![self.add_empty_edge(next, i)];
+            for &rule in self.compiled.rules_for(next) {
+                let completed = DottedRule {
+                    rule,
+                    dot_pos: self.compiled.rule(rule).rhs.len(),
+                };
+                if let Some(&k) = self.chart[i].index.get(&(completed, i)) {
+                    children.push((i, k));
+                }
+            }
 
-        for edge in self.chart.iter() {
-            if edge == &end {
-                end_edges.push(edge.clone());
+            for child in children {
+                let link = Link::Step {
+                    pred: (i, j),
+                    child: Some(child),
+                };
+                self.add_edge(i, d_rule.advance(), span, Some(link));
             }
         }
 
-        end_edges
+        if self.privileged[next.index()] {
+            self.scan(input, i, j, d_rule, span);
+        } else {
+            self.predict(i, next, input.get(i).copied());
+        }
     }
 
-    fn predict(&mut self) {
-        let mut new_edges = Vec::new();
-        for edge in self.chart.iter() {
-            // If the dot is at the RHS of the rule there is nothing to expand
-            if edge.d_rule.rule.1.len() == edge.d_rule.dot_pos {
-                continue;
+    // Adds an edge to an Earley set, returning its reference. If the set already has the edge,
+    // the link is added to it instead as another way of getting there.
+    fn add_edge(
+        &mut self,
+        set: usize,
+        d_rule: DottedRule,
+        span: (usize, usize),
+        link: Option<Link>,
+    ) -> EdgeRef {
+        let next = self
+            .next_symbol(d_rule)
+            .filter(|&next| !self.compiled.is_terminal(next));
+
+        let earley_set = &mut self.chart[set];
+        let key = (d_rule, span.0);
+        if let Some(&j) = earley_set.index.get(&key) {
+            let links = &mut earley_set.edges[j].links;
+            if let Some(link) = link.filter(|link| !links.contains(link)) {
+                links.push(link);
             }
+            return (set, j);
+        }
 
-            // Do not expand any privileged instructions so that the chart doesn't get filled with
-            // rules like N -> they, or V -> fish
-            if self
-                .privileged
-                .contains(&edge.d_rule.rule.1[edge.d_rule.dot_pos])
-            {
-                continue;
+        let j = earley_set.edges.len();
+        if let Some(next) = next {
+            earley_set.waiting.entry(next).or_default().push(j);
+        }
+        earley_set.index.insert(key, j);
+        earley_set.edges.push(Edge {
+            d_rule,
+            span,
+            links: link.into_iter().collect(),
+        });
+
+        (set, j)
+    }
+
+    // Adds the completed edges deriving the empty string from the nullable non-terminal at the
+    // given position, returning the edge for the non-terminal itself
+    fn add_empty_edge(&mut self, non_term: SymbolId, position: usize) -> EdgeRef {
+        let rule = self.compiled.nullable_rule(non_term).unwrap();
+        let span = (position, position);
+
+        // Move the dot over each of the rule's (nullable) symbols in turn
+        let mut edge = self.add_edge(position, DottedRule { rule, dot_pos: 0 }, span, None);
+        for dot_pos in 0..self.compiled.rule(rule).rhs.len() {
+            let symbol = self.compiled.rule(rule).rhs[dot_pos];
+            let child = self.add_empty_edge(symbol, position);
+            let link = Link::Step {
+                pred: edge,
+                child: Some(child),
+            };
+            let d_rule = DottedRule {
+                rule,
+                dot_pos: dot_pos + 1,
+            };
+            edge = self.add_edge(position, d_rule, span, Some(link));
+        }
+
+        edge
+    }
+
+    // Expands the non-terminal into its productions at position i, leaving out those that can't
+    // start with the input symbol there
+    fn predict(&mut self, i: usize, non_term: SymbolId, input_symbol: Option<SymbolId>) {
+        for k in 0..self.compiled.rules_for(non_term).len() {
+            let rule = self.compiled.rules_for(non_term)[k];
+            let viable = match &self.lookahead[rule.index()] {
+                Some(first) => input_symbol.is_some_and(|symbol| first.contains(&symbol)),
+                None => true,
+            };
+            if viable {
+                self.add_edge(i, DottedRule { rule, dot_pos: 0 }, (i, i), None);
             }
+        }
+    }
 
-            // If the dot is in front of a non-terminal,
-            match &edge.d_rule.rule.1[edge.d_rule.dot_pos] {
-                NonTerminal(x) => {
-                    // Expand it all the possible productions from the non-terminal
-                    for rule in self.grammar.get_rules(NonTerminal(x.clone())) {
-                        let new_edge = Edge {
-                            d_rule: DottedRule { rule, dot_pos: 0 },
-                            span: (edge.span.1, edge.span.1),
-                            history: Vec::new(),
-                        };
-
-                        // Do not add duplicate edges
-                        if !self.chart.contains(&new_edge) && !new_edges.contains(&new_edge) {
-                            new_edges.push(new_edge)
-                        }
-                    }
-                }
-                _ => (),
+    // Moves the dot over the input symbol at position i, adding the result to set i + 1
+    fn scan(
+        &mut self,
+        input: &[SymbolId],
+        i: usize,
+        j: usize,
+        d_rule: DottedRule,
+        span: (usize, usize),
+    ) {
+        // If the span is at the end of the input then there is nothing to scan
+        let Some(&input_symbol) = input.get(i) else {
+            return;
+        };
+        let next = self.next_symbol(d_rule).unwrap();
+
+        // If the dot is in front of a privileged non-terminal, check if it reduces to the input
+        // symbol. The privileged rule is added as a completed edge, and completing it moves the
+        // dot of this edge.
+        if self.privileged[next.index()] {
+            if let Some(&rule) = self.terminal_rules.get(&(next, input_symbol)) {
+                let completed = DottedRule { rule, dot_pos: 1 };
+                self.add_edge(i + 1, completed, (i, i + 1), Some(Link::Scanned));
+            }
+        } else if next == input_symbol {
+            let link = Link::Step {
+                pred: (i, j),
+                child: None,
+            };
+            self.add_edge(i + 1, d_rule.advance(), (span.0, i + 1), Some(link));
+        }
+    }
+
+    // Moves the dot over the completed edge's non-terminal for every edge that was waiting on it
+    fn cont(&mut self, i: usize, j: usize, d_rule: DottedRule, span: (usize, usize)) {
+        let origin = span.0;
+        let lhs = self.compiled.rule(d_rule.rule).lhs;
+
+        // Leo's optimisation: if the origin set is deterministic for the non-terminal, add the
+        // topmost item of the chain right away. Sets are only complete once they have been
+        // processed, so this can't be done for empty spans.
+        if origin < i {
+            if let Some((top, start)) = self.leo_item(origin, lhs) {
+                self.add_edge(i, top, (start, i), Some(Link::Leo { child: (i, j) }));
+                return;
             }
         }
 
-        // Update chart with new edges
-        new_edges.into_iter().for_each(|e| self.chart.push(e));
+        let Some(waiting) = self.chart[origin].waiting.get(&lhs) else {
+            return;
+        };
+
+        for k in waiting.clone() {
+            let parent = &self.chart[origin].edges[k];
+            let (parent_rule, start) = (parent.d_rule, parent.span.0);
+            let link = Link::Step {
+                pred: (origin, k),
+                child: Some((i, j)),
+            };
+            self.add_edge(i, parent_rule.advance(), (start, i), Some(link));
+        }
     }
 
-    fn scan(&mut self, input: &Vec<Symbol>) {
-        let mut new_edges = Vec::new();
-        for edge in self.chart.iter() {
-            let rule = &edge.d_rule.rule;
+    // The edge in the set waiting on the non-terminal, if there is exactly one and completing the
+    // non-terminal also completes it
+    fn penultimate_edge(&self, set: usize, non_term: SymbolId) -> Option<EdgeRef> {
+        match self.chart[set].waiting.get(&non_term).map(Vec::as_slice) {
+            Some(&[k]) => {
+                let edge = &self.chart[set].edges[k];
+                let rule = self.compiled.rule(edge.d_rule.rule);
+                (edge.d_rule.dot_pos + 1 == rule.rhs.len()).then_some((set, k))
+            }
+            _ => None,
+        }
+    }
 
-            // If the dot is at the RHS of the rule there is nothing to scan
-            if rule.1.len() == edge.d_rule.dot_pos {
-                continue;
+    // Finds (and memoizes) the topmost item of the deterministic chain that completing the
+    // non-terminal in the given set leads to
+    fn leo_item(&mut self, set: usize, non_term: SymbolId) -> Option<(DottedRule, usize)> {
+        if let Some(&item) = self.chart[set].leo.get(&non_term) {
+            return item;
+        }
+
+        let item = self.penultimate_edge(set, non_term).map(|pred| {
+            let pred = self.edge(pred);
+            let (d_rule, start) = (pred.d_rule, pred.span.0);
+            let completed = (d_rule.advance(), start);
+
+            // Carry on up the chain. Start positions strictly decrease along it, so this stops
+            // even for cyclic unit rules.
+            if start < set {
+                let lhs = self.compiled.rule(d_rule.rule).lhs;
+                self.leo_item(start, lhs).unwrap_or(completed)
+            } else {
+                completed
             }
+        });
+
+        self.chart[set].leo.insert(non_term, item);
+        item
+    }
+
+    // Replaces the Leo links of the edges reachable from the given ones with ordinary links,
+    // adding back the completed edges that the optimisation skipped
+    fn expand_leo_links(&mut self, roots: Vec<EdgeRef>) {
+        let mut visited = HashSet::new();
+        let mut stack = roots;
 
-            // If the span is at the end of the input then there is nothing to scan
-            if edge.span.1 >= input.len() {
+        while let Some(edge_ref) = stack.pop() {
+            if !visited.insert(edge_ref) {
                 continue;
             }
 
-            // If edge has dot in front a privileged non-terminal
-            if self.privileged.contains(&rule.1[edge.d_rule.dot_pos]) {
-                // Check if the privileged non-terminal reduces to the input symbol
-                match self
-                    .grammar
-                    .get_terminal_rule(&rule.1[edge.d_rule.dot_pos], &input[edge.span.1])
-                {
-                    Some(rule) => {
-                        let new_edge = Edge {
-                            d_rule: DottedRule { rule, dot_pos: 1 },
-                            span: (edge.span.0, edge.span.1 + 1),
-                            history: Vec::new(),
-                        };
-
-                        // Don't add duplicate edges
-                        if !new_edges.contains(&new_edge) && !self.chart.contains(&new_edge) {
-                            new_edges.push(new_edge);
-                        }
+            for k in 0..self.edge(edge_ref).links.len() {
+                let link = match self.edge(edge_ref).links[k] {
+                    Link::Leo { child } => {
+                        let link = self.resolve_leo(edge_ref, child, &mut stack);
+                        self.chart[edge_ref.0].edges[edge_ref.1].links[k] = link;
+                        link
                     }
-                    None => (),
-                }
-            }
-
-            // Shift if the edge's rule has a dot in front of a terminal matching the input symbol
-            if &rule.1[edge.d_rule.dot_pos] == &input[edge.span.1] {
-                let new_edge = Edge {
-                    d_rule: DottedRule {
-                        rule: rule.clone(),
-                        dot_pos: edge.d_rule.dot_pos + 1,
-                    },
-                    span: (edge.span.0, edge.span.1 + 1),
-                    history: Vec::new(),
+                    link => link,
                 };
 
-                // Don't add duplicate edges
-                if !new_edges.contains(&new_edge) && !self.chart.contains(&new_edge) {
-                    new_edges.push(new_edge);
+                if let Link::Step { pred, child } = link {
+                    stack.push(pred);
+                    stack.extend(child);
                 }
             }
         }
 
-        // Update chart with new edges
-        new_edges.into_iter().for_each(|e| self.chart.push(e));
+        // Two Leo links of an edge can resolve to the same ordinary link
+        for edge_ref in visited {
+            let links = &mut self.chart[edge_ref.0].edges[edge_ref.1].links;
+            if links.len() > 1 {
+                let mut seen = Vec::new();
+                links.retain(|link| {
+                    let new = !seen.contains(link);
+                    seen.push(*link);
+                    new
+                });
+            }
+        }
     }
 
-    fn cont(&mut self) {
-        let mut chart_len = self.chart.len();
+    // Walks up the deterministic chain from the completed child to the topmost edge, adding the
+    // edges in between, and returns the ordinary link for the topmost edge. The edges linked to
+    // on the way are pushed onto the stack, as they may have Leo links of their own.
+    fn resolve_leo(&mut self, top: EdgeRef, mut child: EdgeRef, stack: &mut Vec<EdgeRef>) -> Link {
+        let i = top.0;
+        let (top_rule, top_start) = (self.edge(top).d_rule, self.edge(top).span.0);
 
-        // Loop until there are no new edges being added
         loop {
-            let mut new_edges = Vec::new();
-            for (i, edge) in self.chart.iter().enumerate() {
-                // Get any edge whose rule has the dot at the very RHS
-                if edge.d_rule.dot_pos == edge.d_rule.rule.1.len() {
-                    for edge_2 in self.chart.iter() {
-                        // Get all the edges whose rule has the dot in front of this
-                        // in front of the same non-terminal as the completed production above
-                        if edge_2.d_rule.dot_pos < edge_2.d_rule.rule.1.len()
-                            && edge_2.d_rule.rule.1[edge_2.d_rule.dot_pos] == edge.d_rule.rule.0
-                            && edge_2.span.1 == edge.span.0
-                        {
-                            // Add the completed edge into the new edge's history
-                            let mut history = edge_2.history.clone();
-                            history.push(i as usize);
-
-                            let new_edge = Edge {
-                                d_rule: DottedRule {
-                                    rule: edge_2.d_rule.rule.clone(),
-                                    dot_pos: edge_2.d_rule.dot_pos + 1,
-                                },
-                                span: (edge_2.span.0, edge.span.1),
-                                history,
-                            };
-
-                            // Don't add duplicate edges
-                            if !self.chart.contains(&new_edge) {
-                                new_edges.push(new_edge);
-                            }
+            let completed = self.edge(child);
+            let lhs = self.compiled.rule(completed.d_rule.rule).lhs;
+            let pred = self.penultimate_edge(completed.span.0, lhs).unwrap();
+            let (pred_rule, start) = (self.edge(pred).d_rule, self.edge(pred).span.0);
+
+            stack.push(pred);
+            stack.push(child);
+            let link = Link::Step {
+                pred,
+                child: Some(child),
+            };
+            let d_rule = pred_rule.advance();
+            if d_rule == top_rule && start == top_start {
+                return link;
+            }
+
+            child = self.add_edge(i, d_rule, (start, i), Some(link));
+        }
+    }
+
+    // Returns the completed edges of any production of the start symbol spanning the whole input
+    fn get_end_edges(&self, end: usize) -> Vec<EdgeRef> {
+        let mut end_edges = Vec::new();
+
+        for (j, edge) in self.chart[end].edges.iter().enumerate() {
+            if self.compiled.rule(edge.d_rule.rule).lhs == self.compiled.start
+                && self.next_symbol(edge.d_rule).is_none()
+                && edge.span.0 == 0
+            {
+                end_edges.push((end, j));
+            }
+        }
+
+        end_edges
+    }
+
+    // Describes where parsing got stuck: the furthest position any edge reached, and the
+    // terminals that edges ending there could have scanned next
+    fn failure(&self, input: &[SymbolId]) -> ParseError {
+        let furthest = self
+            .chart
+            .iter()
+            .rposition(|set| !set.edges.is_empty())
+            .unwrap_or(0);
+
+        ParseError::NoParse {
+            position: furthest,
+            found: input
+                .get(furthest)
+                .map(|&id| self.compiled.symbols.symbol(id).clone()),
+            expected: self.expected_terminals(furthest),
+        }
+    }
+
+    fn expected_terminals(&self, position: usize) -> Vec<Symbol> {
+        let mut expected = Vec::new();
+
+        for edge in self.chart[position].edges.iter() {
+            let Some(next) = self.next_symbol(edge.d_rule) else {
+                continue;
+            };
+
+            if self.privileged[next.index()] {
+                // Privileged non-terminals are scanned directly, so they expect their terminals
+                for &rule in self.compiled.rules_for(next) {
+                    if let [terminal] = self.compiled.rule(rule).rhs[..] {
+                        if self.compiled.is_terminal(terminal) {
+                            expected.push(self.compiled.symbols.symbol(terminal).clone());
                         }
                     }
                 }
+            } else if self.compiled.is_terminal(next) {
+                expected.push(self.compiled.symbols.symbol(next).clone());
+            } else {
+                // The rules of the non-terminal that could have gone on were not predicted
+                let first = &self.first[next.index()];
+                expected.extend(
+                    first
+                        .iter()
+                        .map(|&id| self.compiled.symbols.symbol(id).clone()),
+                );
             }
-            // Update chart with new edges
-            new_edges.into_iter().for_each(|e| self.chart.push(e));
+        }
 
-            // Break if no new edges were added
-            if chart_len == self.chart.len() {
-                break;
-            } else {
-                chart_len = self.chart.len();
+        expected.sort();
+        expected.dedup();
+        expected
+    }
+
+    fn edge(&self, (set, j): EdgeRef) -> &Edge {
+        &self.chart[set].edges[j]
+    }
+
+    // Whether the edge still has a derivation once the grammar's disambiguation filters are
+    // applied. `validity` memoizes whether edges have a derivation at all, holding None for the
+    // edges being worked out, so derivations going round a cycle of the grammar are dropped.
+    fn is_valid(&self, edge_ref: EdgeRef, validity: &mut HashMap<EdgeRef, Option<bool>>) -> bool {
+        if self.filters.is_empty() {
+            return true;
+        }
+        if !self.is_derivable(edge_ref, validity) {
+            return false;
+        }
+
+        let edge = self.edge(edge_ref);
+        if self.next_symbol(edge.d_rule).is_some() {
+            return true;
+        }
+
+        // Compare the edge with the other completed edges of its non-terminal over the same span
+        let (start, end) = edge.span;
+        let rule = edge.d_rule.rule;
+        let mut preferred = false;
+        let mut unavoided = false;
+        for &other in self.compiled.rules_for(self.compiled.rule(rule).lhs) {
+            let completed = DottedRule {
+                rule: other,
+                dot_pos: self.compiled.rule(other).rhs.len(),
+            };
+            let Some(&j) = self.chart[end].index.get(&(completed, start)) else {
+                continue;
+            };
+            if self.filters.is_reject(other) {
+                return false;
+            }
+            if self.is_derivable((end, j), validity) {
+                preferred |= self.filters.is_preferred(other);
+                unavoided |= !self.filters.is_avoided(other);
             }
         }
+
+        if preferred {
+            self.filters.is_preferred(rule)
+        } else {
+            !(unavoided && self.filters.is_avoided(rule))
+        }
+    }
+
+    // Whether the edge has a derivation whose links all pass the filters, leaving aside how it
+    // compares with the other edges of its non-terminal
+    fn is_derivable(
+        &self,
+        edge_ref: EdgeRef,
+        validity: &mut HashMap<EdgeRef, Option<bool>>,
+    ) -> bool {
+        match validity.get(&edge_ref) {
+            Some(&Some(derivable)) => return derivable,
+            Some(None) => return false,
+            None => {}
+        }
+        validity.insert(edge_ref, None);
+
+        let edge = self.edge(edge_ref);
+        let derivable = !(self.next_symbol(edge.d_rule).is_none()
+            && self.filters.is_reject(edge.d_rule.rule))
+            && (edge.d_rule.dot_pos == 0
+                || edge
+                    .links
+                    .iter()
+                    .any(|link| self.is_valid_link(link, validity)));
+
+        validity.insert(edge_ref, Some(derivable));
+        derivable
     }
 
-    fn print_parse_tree(&self, end_edge: Edge) {
-        let mut stack = vec![end_edge];
+    fn is_valid_link(&self, link: &Link, validity: &mut HashMap<EdgeRef, Option<bool>>) -> bool {
+        if self.filters.is_empty() {
+            return true;
+        }
 
-        loop {
-            let edge = stack.pop();
+        match *link {
+            Link::Step { pred, child } => {
+                let pred_rule = self.edge(pred).d_rule;
+                self.is_valid(pred, validity)
+                    && child.is_none_or(|child| {
+                        self.filters.allows_child(
+                            pred_rule.rule,
+                            pred_rule.dot_pos,
+                            self.edge(child).d_rule.rule,
+                        ) && self.is_valid(child, validity)
+                    })
+            }
+            Link::Scanned => true,
+            Link::Leo { .. } => unreachable!("Leo links are expanded after parsing"),
+        }
+    }
 
-            match edge {
-                Some(edge) => {
-                    println!("{:?}", edge.d_rule.rule);
-                    for i in edge.history {
-                        stack.push(self.chart[i].clone());
+    // Turns the edges reachable from the given completed ones into a forest, leaving out the
+    // links the filters rule out. Edges with the dot at the start of a non-empty rule derive
+    // nothing, so they don't become nodes.
+    fn build_forest(
+        &self,
+        input: Vec<SymbolId>,
+        roots: &[EdgeRef],
+        validity: &mut HashMap<EdgeRef, Option<bool>>,
+    ) -> Forest {
+        let mut ids = HashMap::new();
+        let mut order = Vec::new();
+        let mut stack: Vec<EdgeRef> = roots.iter().rev().copied().collect();
+
+        while let Some(edge_ref) = stack.pop() {
+            if ids.contains_key(&edge_ref) {
+                continue;
+            }
+            let links: Vec<Link> = self
+                .edge(edge_ref)
+                .links
+                .iter()
+                .filter(|link| self.is_valid_link(link, validity))
+                .copied()
+                .collect();
+            ids.insert(edge_ref, NodeId(order.len()));
+
+            for link in links.iter().rev() {
+                if let Link::Step { pred, child } = *link {
+                    stack.extend(child);
+                    if self.edge(pred).d_rule.dot_pos > 0 {
+                        stack.push(pred);
                     }
                 }
-                None => break,
             }
+            order.push((edge_ref, links));
         }
+
+        let nodes = order
+            .iter()
+            .map(|(edge_ref, links)| {
+                let edge = self.edge(*edge_ref);
+                let mut packed: Vec<Packed> = links
+                    .iter()
+                    .map(|link| match *link {
+                        Link::Step { pred, child } => Packed {
+                            left: ids.get(&pred).copied(),
+                            right: Some(match child {
+                                Some(child) => Child::Node(ids[&child]),
+                                None => Child::Terminal(edge.span.1 - 1),
+                            }),
+                        },
+                        Link::Scanned => Packed {
+                            left: None,
+                            right: Some(Child::Terminal(edge.span.0)),
+                        },
+                        Link::Leo { .. } => unreachable!("Leo links are expanded after parsing"),
+                    })
+                    .collect();
+                // A completed empty rule has a single, empty, derivation
+                if packed.is_empty() {
+                    packed.push(Packed {
+                        left: None,
+                        right: None,
+                    });
+                }
+
+                Node {
+                    rule: edge.d_rule.rule,
+                    dot: edge.d_rule.dot_pos,
+                    span: edge.span,
+                    packed,
+                }
+            })
+            .collect();
+
+        let roots = roots.iter().map(|edge_ref| ids[edge_ref]).collect();
+        Forest::new(Rc::clone(&self.compiled), input, nodes, roots)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use crate::grammar::Grammar;
+
+    fn parse(grammar: &str, input: &str) -> Vec<String> {
+        let grammar = Grammar::from_bnf_str(grammar).unwrap();
+        let mut parser = grammar.get_parser(HashSet::new()).unwrap();
+        let input = input.split_whitespace().collect();
+        let mut trees: Vec<String> = parser
+            .parse(input)
+            .unwrap()
+            .iter()
+            .map(|tree| tree.to_string())
+            .collect();
+        trees.sort();
+        trees
+    }
+
+    #[test]
+    fn nullable_non_terminals_in_sequence() {
+        let trees = parse(
+            r#"
+            S ::= A A "x"
+            A ::= %empty | B
+            B ::= %empty
+            "#,
+            "x",
+        );
+
+        assert_eq!(
+            trees,
+            vec![
+                "(S (A (B)) (A (B)) x)",
+                "(S (A (B)) (A) x)",
+                "(S (A) (A (B)) x)",
+                "(S (A) (A) x)",
+            ]
+        );
+    }
+
+    #[test]
+    fn optional_and_repeated_elements() {
+        let grammar = r#"
+            S   ::= Det? N PP*
+            PP  ::= "in" N
+            Det ::= "the"
+            N   ::= "dog"
+        "#;
+
+        assert_eq!(parse(grammar, "dog"), vec!["(S (N dog))"]);
+        assert_eq!(
+            parse(grammar, "the dog in dog in dog"),
+            vec!["(S (Det the) (N dog) (PP in (N dog)) (PP in (N dog)))"]
+        );
+    }
+
+    #[test]
+    fn empty_input_with_nullable_start() {
+        let trees = parse(
+            r#"
+            S ::= A B
+            A ::= %empty | "a"
+            B ::= %empty
+            "#,
+            "",
+        );
+
+        assert_eq!(trees, vec!["(S (A) (B))"]);
+    }
+
+    // Right recursion goes through Leo's optimisation, which skips the chain of completed edges
+    // and fills it back in when trees are built
+    #[test]
+    fn right_recursion() {
+        assert_eq!(
+            parse(r#"A ::= "a" A | "a""#, "a a a a"),
+            vec!["(A a (A a (A a (A a))))"]
+        );
+    }
+
+    #[test]
+    fn right_recursion_with_nullable_tail() {
+        let grammar = r#"
+            A ::= "a" A B | "a"
+            B ::= %empty
+        "#;
+
+        assert_eq!(parse(grammar, "a a a"), vec!["(A a (A a (A a) (B)) (B))"]);
+    }
+
+    #[test]
+    fn ambiguous_right_recursion() {
+        let trees = parse(r#"A ::= "a" A | "a" "a" A | "a""#, "a a a a");
+
+        assert_eq!(
+            trees,
+            vec![
+                "(A a (A a (A a (A a))))",
+                "(A a (A a a (A a)))",
+                "(A a a (A a (A a)))",
+            ]
+        );
     }
 }
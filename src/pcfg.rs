@@ -0,0 +1,151 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::error::GrammarError;
+use crate::forest::{Child, Forest, NodeId};
+use crate::grammar::{Grammar, Rule, Symbol};
+use crate::tree::ParseTree;
+
+// Weighted grammars. Every rule has a weight, 1 unless it was given one, and a tree weighs the
+// product of the weights of its rules. In a probabilistic grammar (PCFG) the weights of the rules
+// of each non-terminal are probabilities summing to 1, which is checked for every non-terminal
+// with weighted rules unless the grammar is marked as unnormalised.
+
+// How far a sum of probabilities can be from 1 to allow for rounding
+const TOLERANCE: f64 = 1e-6;
+
+impl Grammar {
+    // Sets the weight of one rule. The sums aren't checked, as the other rules of the
+    // non-terminal may be yet to get theirs, see check_probabilities.
+    pub fn set_weight(&mut self, rule: Rule, weight: f64) -> Result<(), GrammarError> {
+        self.check_rule(&rule)?;
+        if !weight.is_finite() || weight < 0.0 {
+            return Err(GrammarError::InvalidWeight { rule, weight });
+        }
+
+        self.weights_mut().insert(rule, weight);
+        Ok(())
+    }
+
+    // Replaces every weight of the grammar, checking that they are probabilities unless the
+    // grammar is unnormalised. The grammar is left as it was on an error.
+    pub fn set_weights(&mut self, weights: HashMap<Rule, f64>) -> Result<(), GrammarError> {
+        for (rule, &weight) in &weights {
+            self.check_rule(rule)?;
+            if !weight.is_finite() || weight < 0.0 {
+                return Err(GrammarError::InvalidWeight {
+                    rule: rule.clone(),
+                    weight,
+                });
+            }
+        }
+
+        let previous = std::mem::replace(self.weights_mut(), weights);
+        if let Err(error) = self.check_probabilities() {
+            *self.weights_mut() = previous;
+            return Err(error);
+        }
+        Ok(())
+    }
+
+    // Checks that the weights of the rules of every non-terminal with weighted rules sum to 1,
+    // which always passes for an unnormalised grammar
+    pub fn check_probabilities(&self) -> Result<(), GrammarError> {
+        if self.is_unnormalised() {
+            return Ok(());
+        }
+
+        let mut weighted: Vec<&Symbol> = self
+            .get_weights()
+            .keys()
+            .map(|rule| &rule.0)
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .collect();
+        weighted.sort();
+
+        for non_terminal in weighted {
+            let sum = self.weight_sum(non_terminal);
+            if (sum - 1.0).abs() > TOLERANCE {
+                return Err(GrammarError::NotNormalised {
+                    non_terminal: non_terminal.clone(),
+                    sum,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    // Scales the weights of the rules of every non-terminal to sum to 1, giving every rule a
+    // weight. Non-terminals whose rules all weigh 0 are left alone.
+    pub fn normalise_weights(&mut self) {
+        let mut weights = HashMap::new();
+        for rule in self.get_all_rules() {
+            let sum = self.weight_sum(&rule.0);
+            let weight = self.get_weight(rule);
+            weights.insert(rule.clone(), if sum > 0.0 { weight / sum } else { weight });
+        }
+
+        *self.weights_mut() = weights;
+    }
+
+    fn weight_sum(&self, non_terminal: &Symbol) -> f64 {
+        self.get_all_rules()
+            .iter()
+            .filter(|rule| &rule.0 == non_terminal)
+            .map(|rule| self.get_weight(rule))
+            .sum()
+    }
+}
+
+impl Forest {
+    // Finds the most probable tree of the forest, the Viterbi parse, along with the natural log
+    // of its weight. Derivations going round a cycle of the grammar are left out.
+    pub fn get_best_tree(&self) -> Option<(ParseTree, f64)> {
+        let mut best = vec![None; self.len()];
+        let &root = self.get_roots().iter().max_by(|&&a, &&b| {
+            let a = self.best_score(a, &mut best);
+            let b = self.best_score(b, &mut best);
+            a.total_cmp(&b)
+        })?;
+        let score = self.best_score(root, &mut best);
+
+        let chosen: Vec<usize> = best
+            .iter()
+            .map(|best| best.flatten().map_or(0, |(_, k)| k))
+            .collect();
+        let tree = self
+            .build_chosen_tree(root, &chosen)
+            .expect("the best derivation of a node is never cyclic");
+        Some((tree, score))
+    }
+
+    // The log weight of the best derivation of the node. `best` memoizes it along with the packed
+    // node it comes from, holding Some(None) for the nodes being worked out.
+    fn best_score(&self, id: NodeId, best: &mut Vec<Option<Option<(f64, usize)>>>) -> f64 {
+        match best[id.0] {
+            Some(Some((score, _))) => return score,
+            Some(None) => return f64::NEG_INFINITY,
+            None => {}
+        }
+        best[id.0] = Some(None);
+
+        let mut chosen = (f64::NEG_INFINITY, 0);
+        for (k, packed) in self.get_alternatives(id).iter().enumerate() {
+            let left = packed.left.map_or(0.0, |left| self.best_score(left, best));
+            let right = match packed.right {
+                Some(Child::Node(child)) => self.best_score(child, best),
+                _ => 0.0,
+            };
+            if k == 0 || left + right > chosen.0 {
+                chosen = (left + right, k);
+            }
+        }
+
+        // The rule's weight counts once, at its complete node
+        if self.is_complete(id) {
+            chosen.0 += self.get_grammar().log_weight(self.get_node(id).rule);
+        }
+        best[id.0] = Some(Some(chosen));
+        chosen.0
+    }
+}
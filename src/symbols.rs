@@ -0,0 +1,184 @@
+use std::collections::HashMap;
+
+use crate::analysis::GrammarAnalysis;
+use crate::grammar::{Grammar, Rule, Symbol};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct SymbolId(pub u32);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct RuleId(pub u32);
+
+impl SymbolId {
+    pub fn index(self) -> usize {
+        self.0 as usize
+    }
+}
+
+impl RuleId {
+    pub fn index(self) -> usize {
+        self.0 as usize
+    }
+}
+
+// Maps symbols to dense integer ids and back
+#[derive(Debug, Default, Clone)]
+pub struct SymbolTable {
+    symbols: Vec<Symbol>,
+    ids: HashMap<Symbol, SymbolId>,
+}
+
+impl SymbolTable {
+    pub fn intern(&mut self, symbol: &Symbol) -> SymbolId {
+        if let Some(&id) = self.ids.get(symbol) {
+            return id;
+        }
+
+        let id = SymbolId(self.symbols.len() as u32);
+        self.symbols.push(symbol.clone());
+        self.ids.insert(symbol.clone(), id);
+        id
+    }
+
+    pub fn id(&self, symbol: &Symbol) -> Option<SymbolId> {
+        self.ids.get(symbol).copied()
+    }
+
+    pub fn symbol(&self, id: SymbolId) -> &Symbol {
+        &self.symbols[id.index()]
+    }
+
+    pub fn len(&self) -> usize {
+        self.symbols.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.symbols.is_empty()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CompiledRule {
+    pub lhs: SymbolId,
+    pub rhs: Vec<SymbolId>,
+}
+
+// A grammar with its symbols and rules turned into ids, which is what the parser works on. Ids
+// are handed out in sorted order, so compiling the same grammar always gives the same ids.
+#[derive(Debug, Clone)]
+pub struct CompiledGrammar {
+    pub symbols: SymbolTable,
+    pub rules: Vec<CompiledRule>,
+    pub start: SymbolId,
+    // S' -> S, added after the grammar's own rules to seed the parser with every production of
+    // the start symbol S
+    pub augmented_rule: RuleId,
+    // Indexed by SymbolId
+    rules_by_lhs: Vec<Vec<RuleId>>,
+    terminal: Vec<bool>,
+    hidden: Vec<bool>,
+    // A rule deriving the empty string for each nullable non-terminal
+    nullable: Vec<Option<RuleId>>,
+    // Natural log of the weight of each rule, indexed by RuleId
+    log_weights: Vec<f64>,
+}
+
+impl CompiledGrammar {
+    pub fn new(grammar: &Grammar, analysis: &GrammarAnalysis) -> CompiledGrammar {
+        let mut symbols = SymbolTable::default();
+
+        let mut non_terminals: Vec<&Symbol> = grammar.get_non_terminals().iter().collect();
+        non_terminals.sort();
+        let mut terminals: Vec<&Symbol> = grammar.get_terminals().iter().collect();
+        terminals.sort();
+        for symbol in non_terminals.into_iter().chain(terminals) {
+            symbols.intern(symbol);
+        }
+
+        let mut sorted_rules: Vec<&Rule> = grammar.get_all_rules().iter().collect();
+        sorted_rules.sort();
+        let augmented = grammar.get_augmented_rule();
+        let rules: Vec<CompiledRule> = sorted_rules
+            .iter()
+            .copied()
+            .chain([&augmented])
+            .map(|rule| CompiledRule {
+                lhs: symbols.intern(&rule.0),
+                rhs: rule.1.iter().map(|symbol| symbols.intern(symbol)).collect(),
+            })
+            .collect();
+
+        let mut rules_by_lhs = vec![Vec::new(); symbols.len()];
+        for (id, rule) in rules.iter().enumerate() {
+            rules_by_lhs[rule.lhs.index()].push(RuleId(id as u32));
+        }
+
+        let nullable = (0..symbols.len())
+            .map(|i| {
+                let rule = analysis.get_nullable_rule(symbols.symbol(SymbolId(i as u32)))?;
+                let id = sorted_rules.binary_search(&rule).unwrap();
+                Some(RuleId(id as u32))
+            })
+            .collect();
+
+        let terminal = (0..symbols.len())
+            .map(|i| matches!(symbols.symbol(SymbolId(i as u32)), Symbol::Terminal(_)))
+            .collect();
+        let log_weights = sorted_rules
+            .iter()
+            .map(|rule| grammar.get_weight(rule).ln())
+            .chain([0.0])
+            .collect();
+        let hidden = (0..symbols.len())
+            .map(|i| grammar.is_hidden(symbols.symbol(SymbolId(i as u32))))
+            .collect();
+
+        CompiledGrammar {
+            start: symbols.intern(grammar.get_start()),
+            augmented_rule: RuleId(rules.len() as u32 - 1),
+            symbols,
+            rules,
+            rules_by_lhs,
+            terminal,
+            hidden,
+            nullable,
+            log_weights,
+        }
+    }
+
+    pub fn rule(&self, id: RuleId) -> &CompiledRule {
+        &self.rules[id.index()]
+    }
+
+    pub fn rules_for(&self, lhs: SymbolId) -> &[RuleId] {
+        &self.rules_by_lhs[lhs.index()]
+    }
+
+    pub fn is_terminal(&self, symbol: SymbolId) -> bool {
+        self.terminal[symbol.index()]
+    }
+
+    pub fn is_hidden(&self, symbol: SymbolId) -> bool {
+        self.hidden[symbol.index()]
+    }
+
+    pub fn nullable_rule(&self, symbol: SymbolId) -> Option<RuleId> {
+        self.nullable[symbol.index()]
+    }
+
+    pub fn log_weight(&self, rule: RuleId) -> f64 {
+        self.log_weights[rule.index()]
+    }
+
+    // Converts a compiled rule back into the grammar's representation
+    pub fn to_rule(&self, id: RuleId) -> Rule {
+        let rule = self.rule(id);
+        (
+            self.symbols.symbol(rule.lhs).clone(),
+            rule.rhs
+                .iter()
+                .map(|&symbol| self.symbols.symbol(symbol).clone())
+                .collect(),
+        )
+    }
+}
@@ -0,0 +1,232 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::error::ParseError;
+use crate::forest::{Child, Forest, NodeId};
+use crate::grammar::{Grammar, Rule, Symbol};
+use crate::symbols::RuleId;
+
+// Training the weights of a grammar on sentences without trees, by expectation maximisation with
+// the inside-outside algorithm over their parse forests. Each iteration works out how often each
+// rule is expected to be used in the parses of the sentences, weighing the parses by their
+// probability under the current weights, and then sets the weight of each rule to its share of
+// the expected uses of its non-terminal's rules. The likelihood of the corpus never goes down
+// from one iteration to the next. As elsewhere, derivations going round a cycle of the grammar
+// are left out.
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Training {
+    // The natural log of the probability of the parsed sentences before each iteration
+    pub log_likelihoods: Vec<f64>,
+    // Indices of the sentences that couldn't be parsed, which are left out
+    pub unparsed: Vec<usize>,
+}
+
+impl Grammar {
+    // Runs the given number of iterations of inside-outside training on the corpus, setting the
+    // weights of the rules of every non-terminal used in its parses to probabilities. The weights
+    // are normalised first, so that the first iteration starts from a probability distribution;
+    // the weights of the other non-terminals' rules are left at that.
+    pub fn train(
+        &mut self,
+        corpus: &[Vec<&str>],
+        privileged: HashSet<Symbol>,
+        iterations: usize,
+    ) -> Result<Training, ParseError> {
+        let mut training = Training {
+            log_likelihoods: Vec::new(),
+            unparsed: Vec::new(),
+        };
+
+        self.normalise_weights();
+        for iteration in 0..iterations {
+            let mut counts = HashMap::new();
+            let mut log_likelihood = 0.0;
+            let mut parser = self.get_parser(privileged.clone())?;
+            for (index, sentence) in corpus.iter().enumerate() {
+                match parser.parse_forest(sentence.clone()) {
+                    Ok(forest) => log_likelihood += add_expected_counts(&forest, &mut counts),
+                    Err(_) if iteration == 0 => training.unparsed.push(index),
+                    Err(_) => {}
+                }
+            }
+
+            training.log_likelihoods.push(log_likelihood);
+            self.reestimate(&counts);
+        }
+
+        Ok(training)
+    }
+
+    // Sets the weight of each rule to its share of the expected uses of its non-terminal's rules
+    fn reestimate(&mut self, counts: &HashMap<Rule, f64>) {
+        let mut totals: HashMap<&Symbol, f64> = HashMap::new();
+        for (rule, count) in counts {
+            *totals.entry(&rule.0).or_default() += count;
+        }
+
+        let mut weights = Vec::new();
+        for rule in self.get_all_rules() {
+            if let Some(&total) = totals.get(&rule.0).filter(|&&total| total > 0.0) {
+                let count = counts.get(rule).copied().unwrap_or(0.0);
+                weights.push((rule.clone(), count / total));
+            }
+        }
+        self.weights_mut().extend(weights);
+    }
+}
+
+// Adds the expected number of uses of each rule in the parses of the forest to `counts`,
+// returning the natural log of the total probability of the parses
+fn add_expected_counts(forest: &Forest, counts: &mut HashMap<Rule, f64>) -> f64 {
+    let grammar = forest.get_grammar();
+    let own_weight = |id: NodeId| {
+        if forest.is_complete(id) {
+            grammar.log_weight(forest.get_node(id).rule)
+        } else {
+            0.0
+        }
+    };
+
+    let mut inside = vec![None; forest.len()];
+    let mut order = Vec::new();
+    let mut total = f64::NEG_INFINITY;
+    for &root in forest.get_roots() {
+        total = log_add(total, inside_score(forest, root, &mut inside, &mut order));
+    }
+    if total == f64::NEG_INFINITY {
+        return total;
+    }
+
+    // A node's inside score only takes in the children that were finished before it, which
+    // leaves out the edges that close a cycle
+    let mut finished = vec![usize::MAX; forest.len()];
+    for (position, &id) in order.iter().enumerate() {
+        finished[id.0] = position;
+    }
+    let is_used = |parent: NodeId, child: NodeId| finished[child.0] < finished[parent.0];
+    let inside = |id: NodeId| inside[id.0].unwrap_or(f64::NEG_INFINITY);
+
+    // Parents are finished after their children, so going through the nodes in the reverse
+    // order gives every node its whole outside score before it passes it on
+    let mut outside = vec![f64::NEG_INFINITY; forest.len()];
+    for &root in forest.get_roots() {
+        outside[root.0] = 0.0;
+    }
+    let mut rule_counts = vec![0.0; grammar.rules.len()];
+    for &id in order.iter().rev() {
+        if outside[id.0] == f64::NEG_INFINITY {
+            continue;
+        }
+        if forest.is_complete(id) {
+            let rule = forest.get_node(id).rule;
+            rule_counts[rule.index()] += (inside(id) + outside[id.0] - total).exp();
+        }
+
+        let above = outside[id.0] + own_weight(id);
+        for packed in forest.get_alternatives(id) {
+            let left = packed.left;
+            let right = match packed.right {
+                Some(Child::Node(right)) => Some(right),
+                _ => None,
+            };
+            if left
+                .into_iter()
+                .chain(right)
+                .any(|child| !is_used(id, child))
+            {
+                continue;
+            }
+
+            if let Some(left) = left {
+                let sibling = right.map_or(0.0, inside);
+                outside[left.0] = log_add(outside[left.0], above + sibling);
+            }
+            if let Some(right) = right {
+                let sibling = left.map_or(0.0, inside);
+                outside[right.0] = log_add(outside[right.0], above + sibling);
+            }
+        }
+    }
+
+    for (id, &count) in rule_counts.iter().enumerate() {
+        if count > 0.0 {
+            *counts
+                .entry(grammar.to_rule(RuleId(id as u32)))
+                .or_default() += count;
+        }
+    }
+    total
+}
+
+// The natural log of the total weight of the node's derivations. `inside` memoizes it, holding
+// negative infinity for the nodes being worked out, and `order` gets the nodes as they're
+// finished.
+fn inside_score(
+    forest: &Forest,
+    id: NodeId,
+    inside: &mut Vec<Option<f64>>,
+    order: &mut Vec<NodeId>,
+) -> f64 {
+    if let Some(score) = inside[id.0] {
+        return score;
+    }
+    inside[id.0] = Some(f64::NEG_INFINITY);
+
+    let mut score = f64::NEG_INFINITY;
+    for packed in forest.get_alternatives(id) {
+        let left = packed
+            .left
+            .map_or(0.0, |left| inside_score(forest, left, inside, order));
+        let right = match packed.right {
+            Some(Child::Node(right)) => inside_score(forest, right, inside, order),
+            _ => 0.0,
+        };
+        score = log_add(score, left + right);
+    }
+    if forest.is_complete(id) {
+        score += forest.get_grammar().log_weight(forest.get_node(id).rule);
+    }
+
+    inside[id.0] = Some(score);
+    order.push(id);
+    score
+}
+
+// ln(e^a + e^b) without leaving the log space
+fn log_add(a: f64, b: f64) -> f64 {
+    if a == f64::NEG_INFINITY {
+        return b;
+    }
+    if b == f64::NEG_INFINITY {
+        return a;
+    }
+    let (high, low) = if a > b { (a, b) } else { (b, a) };
+    high + (low - high).exp().ln_1p()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use crate::grammar::Grammar;
+
+    #[test]
+    fn log_likelihood_never_goes_down() {
+        let mut grammar = Grammar::from_bnf_str(r#"S ::= S S | "a" | "b""#).unwrap();
+        let corpus = vec![vec!["a", "b"], vec!["a", "a", "b"], vec!["a"]];
+
+        let training = grammar.train(&corpus, HashSet::new(), 10).unwrap();
+
+        assert!(training.unparsed.is_empty());
+        for window in training.log_likelihoods.windows(2) {
+            assert!(
+                window[0] <= window[1] + 1e-9,
+                "{:?}",
+                training.log_likelihoods
+            );
+        }
+        for &log_likelihood in &training.log_likelihoods {
+            assert!(log_likelihood <= 0.0, "{:?}", training.log_likelihoods);
+        }
+    }
+}
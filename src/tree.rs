@@ -0,0 +1,90 @@
+use std::fmt;
+
+use crate::grammar::{Rule, Symbol};
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+// A node of a parse tree. Leaves are the terminals of the input, every other node is labelled
+// with a non-terminal and the rule it was expanded with. Hidden helper non-terminals are spliced
+// out, so the rule's right side lines up with the node's children rather than with the grammar.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ParseTree {
+    pub label: Symbol,
+    pub rule: Option<Rule>,
+    // Start and end position of the input covered by the node
+    pub span: (usize, usize),
+    pub children: Vec<ParseTree>,
+}
+
+impl ParseTree {
+    pub fn leaf(terminal: Symbol, position: usize) -> ParseTree {
+        ParseTree {
+            label: terminal,
+            rule: None,
+            span: (position, position + 1),
+            children: Vec::new(),
+        }
+    }
+
+    pub fn is_leaf(&self) -> bool {
+        self.rule.is_none()
+    }
+
+    // Returns the terminals at the leaves of the tree, from left to right
+    pub fn leaves(&self) -> Vec<&Symbol> {
+        if self.is_leaf() {
+            return vec![&self.label];
+        }
+        self.children
+            .iter()
+            .flat_map(|child| child.leaves())
+            .collect()
+    }
+
+    fn fmt_indented(&self, f: &mut fmt::Formatter, depth: usize) -> fmt::Result {
+        if self.is_leaf() {
+            return write!(f, "{}", label_text(&self.label));
+        }
+
+        write!(f, "({}", label_text(&self.label))?;
+        // Pre-terminals are kept on one line
+        if self.children.iter().all(ParseTree::is_leaf) {
+            for child in &self.children {
+                write!(f, " {}", label_text(&child.label))?;
+            }
+        } else {
+            for child in &self.children {
+                write!(f, "\n{:width$}", "", width = (depth + 1) * 2)?;
+                child.fmt_indented(f, depth + 1)?;
+            }
+        }
+        write!(f, ")")
+    }
+}
+
+fn label_text(symbol: &Symbol) -> &str {
+    match symbol {
+        Symbol::NonTerminal(name) | Symbol::Terminal(name) => name,
+    }
+}
+
+// Prints the tree in bracketed form, e.g. (S (NP (N they)) (VP (V fish))), or spread over
+// indented lines with {:#}
+impl fmt::Display for ParseTree {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if f.alternate() {
+            return self.fmt_indented(f, 0);
+        }
+
+        if self.is_leaf() {
+            return write!(f, "{}", label_text(&self.label));
+        }
+        write!(f, "({}", label_text(&self.label))?;
+        for child in &self.children {
+            write!(f, " {}", child)?;
+        }
+        write!(f, ")")
+    }
+}
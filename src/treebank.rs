@@ -0,0 +1,256 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::path::Path;
+
+use crate::error::GrammarError;
+use crate::grammar::{Grammar, Rule, Symbol};
+use crate::tree::ParseTree;
+
+// Treebanks in the bracketed format of the Penn Treebank, one tree after another:
+//
+//     (S (NP (N they)) (VP (V fish)))
+//     ( (S (NP (N they)) (VP (V can) (NP (N fish)))) )
+//
+// A node is a label followed by its children, and a word is any other run of characters without
+// spaces or brackets. The unlabelled brackets the Penn Treebank wraps each tree in are dropped.
+
+struct Reader<'s> {
+    chars: std::iter::Peekable<std::str::Chars<'s>>,
+    line: usize,
+    column: usize,
+    // The position in the sentence of the next word
+    position: usize,
+}
+
+fn error_at(line: usize, column: usize, message: &str) -> GrammarError {
+    GrammarError::Syntax {
+        path: None,
+        line,
+        column,
+        message: message.to_string(),
+    }
+}
+
+impl Reader<'_> {
+    fn peek(&mut self) -> Option<char> {
+        self.chars.peek().copied()
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.chars.next();
+        if c == Some('\n') {
+            self.line += 1;
+            self.column = 1;
+        } else if c.is_some() {
+            self.column += 1;
+        }
+        c
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.peek().is_some_and(char::is_whitespace) {
+            self.bump();
+        }
+    }
+
+    fn word(&mut self) -> String {
+        let mut word = String::new();
+        while let Some(c) = self
+            .peek()
+            .filter(|&c| !c.is_whitespace() && c != '(' && c != ')')
+        {
+            word.push(c);
+            self.bump();
+        }
+        word
+    }
+
+    // Reads a tree, starting at its opening bracket
+    fn tree(&mut self) -> Result<ParseTree, GrammarError> {
+        let (line, column) = (self.line, self.column);
+        self.bump();
+        self.skip_whitespace();
+        let label = match self.peek() {
+            Some('(' | ')') | None => None,
+            Some(_) => Some(self.word()),
+        };
+
+        let mut children = Vec::new();
+        loop {
+            self.skip_whitespace();
+            match self.peek() {
+                Some('(') => children.push(self.tree()?),
+                Some(')') => {
+                    self.bump();
+                    break;
+                }
+                Some(_) => {
+                    let word = Symbol::Terminal(self.word());
+                    children.push(ParseTree::leaf(word, self.position));
+                    self.position += 1;
+                }
+                None => return Err(error_at(line, column, "unclosed bracket")),
+            }
+        }
+
+        let Some(label) = label else {
+            return match <[ParseTree; 1]>::try_from(children) {
+                Ok([child]) if !child.is_leaf() => Ok(child),
+                _ => Err(error_at(
+                    line,
+                    column,
+                    "unlabelled brackets can only wrap a single tree",
+                )),
+            };
+        };
+        let (Some(first), Some(last)) = (children.first(), children.last()) else {
+            return Err(error_at(line, column, "a node needs at least one child"));
+        };
+
+        let label = Symbol::NonTerminal(label);
+        Ok(ParseTree {
+            rule: Some((
+                label.clone(),
+                children.iter().map(|child| child.label.clone()).collect(),
+            )),
+            label,
+            span: (first.span.0, last.span.1),
+            children,
+        })
+    }
+}
+
+pub fn read_treebank(source: &str) -> Result<Vec<ParseTree>, GrammarError> {
+    let mut reader = Reader {
+        chars: source.chars().peekable(),
+        line: 1,
+        column: 1,
+        position: 0,
+    };
+
+    let mut trees = Vec::new();
+    loop {
+        reader.skip_whitespace();
+        match reader.peek() {
+            Some('(') => {
+                reader.position = 0;
+                trees.push(reader.tree()?);
+            }
+            Some(_) => {
+                return Err(error_at(
+                    reader.line,
+                    reader.column,
+                    "expected ( to start a tree",
+                ))
+            }
+            None => return Ok(trees),
+        }
+    }
+}
+
+pub fn read_treebank_file<P: AsRef<Path>>(path: P) -> Result<Vec<ParseTree>, GrammarError> {
+    let path = path.as_ref();
+    let source = fs::read_to_string(path).map_err(|error| GrammarError::Io {
+        path: path.to_path_buf(),
+        error,
+    })?;
+    read_treebank(&source).map_err(|error| error.in_file(path))
+}
+
+// A grammar read off a treebank, along with its pre-terminals, the non-terminals that only ever
+// derive a single word. Those are the symbols to parse with as privileged.
+#[derive(Debug)]
+pub struct TreebankGrammar {
+    pub grammar: Grammar,
+    pub privileged: HashSet<Symbol>,
+    // The TOP symbol added over the roots when they have different labels. It isn't part of the
+    // trees, so evaluation leaves it out.
+    pub added_root: Option<Symbol>,
+}
+
+impl Grammar {
+    // Builds the grammar of the rules used in the trees, weighing each rule by how often it's used
+    // out of the uses of its non-terminal's rules. The start symbol is the label of the roots,
+    // or a new TOP symbol deriving each of them if they differ.
+    pub fn from_treebank(trees: &[ParseTree]) -> Result<TreebankGrammar, GrammarError> {
+        let mut non_terminals = HashSet::new();
+        let mut terminals = HashSet::new();
+        let mut counts: HashMap<Rule, usize> = HashMap::new();
+        let mut roots: BTreeMap<&Symbol, usize> = BTreeMap::new();
+        for tree in trees {
+            add_rules(tree, &mut non_terminals, &mut terminals, &mut counts);
+            if !tree.is_leaf() {
+                *roots.entry(&tree.label).or_default() += 1;
+            }
+        }
+
+        let mut added_root = None;
+        let start = match roots.keys().collect::<Vec<_>>()[..] {
+            [] => return Err(GrammarError::EmptyTreebank),
+            [&start] => start.clone(),
+            _ => {
+                let mut name = "TOP".to_string();
+                while non_terminals.contains(&Symbol::NonTerminal(name.clone())) {
+                    name.push('\'');
+                }
+                let start = Symbol::NonTerminal(name);
+                for (&root, &count) in &roots {
+                    counts.insert((start.clone(), vec![root.clone()]), count);
+                }
+                non_terminals.insert(start.clone());
+                added_root = Some(start.clone());
+                start
+            }
+        };
+
+        // Non-terminals with a rule that isn't a single word aren't pre-terminals
+        let mut privileged: HashSet<Symbol> = counts.keys().map(|rule| rule.0.clone()).collect();
+        for rule in counts.keys() {
+            if !matches!(rule.1[..], [Symbol::Terminal(_)]) {
+                privileged.remove(&rule.0);
+            }
+        }
+
+        let mut totals: HashMap<&Symbol, usize> = HashMap::new();
+        for (rule, &count) in &counts {
+            *totals.entry(&rule.0).or_default() += count;
+        }
+        let weights: HashMap<Rule, f64> = counts
+            .iter()
+            .map(|(rule, &count)| (rule.clone(), count as f64 / totals[&rule.0] as f64))
+            .collect();
+
+        let rules = counts.keys().cloned().collect();
+        let mut grammar = Grammar::new(non_terminals, terminals, start, rules)?;
+        *grammar.weights_mut() = weights;
+
+        Ok(TreebankGrammar {
+            grammar,
+            privileged,
+            added_root,
+        })
+    }
+}
+
+fn add_rules(
+    tree: &ParseTree,
+    non_terminals: &mut HashSet<Symbol>,
+    terminals: &mut HashSet<Symbol>,
+    counts: &mut HashMap<Rule, usize>,
+) {
+    if tree.is_leaf() {
+        terminals.insert(tree.label.clone());
+        return;
+    }
+
+    non_terminals.insert(tree.label.clone());
+    let right_side = tree
+        .children
+        .iter()
+        .map(|child| child.label.clone())
+        .collect();
+    *counts.entry((tree.label.clone(), right_side)).or_default() += 1;
+    for child in &tree.children {
+        add_rules(child, non_terminals, terminals, counts);
+    }
+}